@@ -0,0 +1,24 @@
+/* Runtime linked into executables built by `simple_math -c`. */
+#include <stdio.h>
+#include <stdlib.h>
+
+/* Prints the result the same way the interpreter does: with the fewest
+ * digits that still read back as the same double. */
+void simple_math_print(double result)
+{
+    char buf[32];
+    for (int precision = 1; precision <= 17; precision++) {
+        snprintf(buf, sizeof buf, "%.*g", precision, result);
+        if (strtod(buf, NULL) == result)
+            break;
+    }
+    printf("result = %s\n", buf);
+}
+
+/* Ordered comparisons for `<`, `<=`, `>` and `>=`. QBE has float
+ * comparisons for these, but the qbe crate can only spell the integer
+ * ones, so compiled programs call these instead. */
+double simple_math_lt(double a, double b) { return a < b; }
+double simple_math_le(double a, double b) { return a <= b; }
+double simple_math_gt(double a, double b) { return a > b; }
+double simple_math_ge(double a, double b) { return a >= b; }
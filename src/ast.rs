@@ -1,4 +1,4 @@
-use crate::{lexer::*, error::ParsingError};
+use crate::{constants::constant, error::ParsingError, lexer::*, units::{Overflow, Unit}};
 use std::fmt::Display;
 
 #[derive(Debug, Clone)]
@@ -6,33 +6,243 @@ pub struct Program {
     pub body: Vec<Assign>,
 }
 
+impl Program {
+    /// The program written back as source, one assignment per line, with
+    /// the comments before it above it and those on its line after it.
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        for a in &self.body {
+            let (before, after): (Vec<_>, Vec<_>) = a.comments.iter().partition(|c| c.span.line < a.span.line);
+            for c in before {
+                out += &format!("{}\n", c.text);
+            }
+            out += &a.to_source();
+            for c in after {
+                out += &format!(" {}", c.text);
+            }
+            out += "\n";
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// The variables and functions the expression refers to, each once, in
+    /// the order they first appear.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.collect_names(&mut names);
+        names
+    }
+
+    /// The expression written back as source, with only the parentheses it
+    /// needs besides those of a `Parenthed`.
+    pub fn to_source(&self) -> String {
+        self.source().0
+    }
+
+    /// The source, and the binding powers an operator needs to split it
+    /// on its left and on its right: `a + b` followed by `* c` needs
+    /// parentheses, as `*` binds tighter than `+`.
+    fn source(&self) -> (String, f32, f32) {
+        const ATOM: f32 = f32::INFINITY;
+        let list = |exprs: &[Expression]| exprs.iter().map(Expression::to_source).collect::<Vec<_>>().join(", ");
+        match &self.kind {
+            ExprKind::Var(name) => (name.clone(), ATOM, ATOM),
+            ExprKind::Num(n) => (n.to_string(), ATOM, ATOM),
+            ExprKind::Quantity(n, unit) => (format!("{n} {unit}"), ATOM, ATOM),
+            ExprKind::Parenthed(e) => (format!("({})", e.to_source()), ATOM, ATOM),
+            ExprKind::Array(elements) => (format!("[{}]", list(elements)), ATOM, ATOM),
+            ExprKind::Call(name, args) => (format!("{name}({})", list(args)), ATOM, ATOM),
+            // `to` only applies to a whole expression
+            ExprKind::Convert(e, unit) => (format!("{} to {unit}", e.to_source()), 0., 0.),
+            ExprKind::If(cond, then, otherwise) => {
+                let text = format!("if {} then {} else {}", cond.to_source(), then.to_source(), otherwise.to_source());
+                (text, ATOM, 0.)
+            }
+            ExprKind::Operation(op, operands) if operands.len() == 1 => {
+                let (_, bp) = unary_binding_power(op);
+                let (operand, _, r) = operands[0].wrapped(|l, _| l < bp);
+                let space = if operand.starts_with(['+', '-']) { " " } else { "" };
+                (format!("{op}{space}{operand}"), ATOM, r.min(bp))
+            }
+            ExprKind::Operation(op, operands) => {
+                let (bp_l, bp_r) = binding_power(op).expect("operations of two operands are binary");
+                let (lhs, l, _) = operands[0].wrapped(|_, r| r < bp_l);
+                let (rhs, _, r) = operands[1].wrapped(|l, _| l < bp_r);
+                // products and powers of simple operands are written tight, `2*pi*r`
+                let tight = matches!(op.as_str(), "*" | "/" | "^") && !spaced(&lhs) && !spaced(&rhs);
+                let text = if tight { format!("{lhs}{op}{rhs}") } else { format!("{lhs} {op} {rhs}") };
+                (text, l.min(bp_l), r.min(bp_r))
+            }
+        }
+    }
+
+    /// The source, in parentheses if `needs` says so of its binding powers.
+    fn wrapped(&self, needs: impl Fn(f32, f32) -> bool) -> (String, f32, f32) {
+        let (text, l, r) = self.source();
+        match needs(l, r) {
+            true => (format!("({text})"), f32::INFINITY, f32::INFINITY),
+            false => (text, l, r),
+        }
+    }
+
+    fn collect_names(&self, names: &mut Vec<String>) {
+        let mut add = |name: &String| {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        };
+        match &self.kind {
+            ExprKind::Var(name) => add(name),
+            // the variable `solve` solves for is its own, not the one outside
+            ExprKind::Call(name, args) if name == "solve" && is_equation(args) => {
+                add(name);
+                let mut inner = Vec::new();
+                args.iter().for_each(|a| a.collect_names(&mut inner));
+                if let Some(Expression { kind: ExprKind::Var(x), .. }) = args.get(1) {
+                    inner.retain(|n| n != x);
+                }
+                inner.iter().for_each(add);
+            }
+            ExprKind::Call(name, args) => {
+                add(name);
+                args.iter().for_each(|a| a.collect_names(names));
+            }
+            ExprKind::Num(_) | ExprKind::Quantity(..) => {}
+            ExprKind::Convert(e, _) | ExprKind::Parenthed(e) => e.collect_names(names),
+            ExprKind::Array(exprs) | ExprKind::Operation(_, exprs) => exprs.iter().for_each(|e| e.collect_names(names)),
+            ExprKind::If(cond, then, otherwise) => {
+                for e in [cond, then, otherwise] {
+                    e.collect_names(names);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub enum Expression {
+pub enum ExprKind {
     Var(String),
-    Num(f64),
+    Num(Number),
+    /// A number with a unit, `9.81 m/s^2`.
+    Quantity(Number, Unit),
+    /// `speed to km/h`
+    Convert(Box<Expression>, Unit),
     Parenthed(Box<Expression>),
+    /// `[1, 2, 3]`, or a matrix as a list of rows, `[[1, 2], [3, 4]]`.
+    Array(Vec<Expression>),
     Operation(String, Vec<Expression>),
+    Call(String, Vec<Expression>),
+    If(Box<Expression>, Box<Expression>, Box<Expression>),
 }
 
 impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Display for ExprKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Expression::Var(s) => write!(f, "var '{s}'"),
-            Expression::Num(i) => write!(f, "{i}"),
-            Expression::Parenthed(a) => write!(f, "({a})"),
-            Expression::Operation(op, e) => {
+            ExprKind::Var(s) => write!(f, "var '{s}'"),
+            ExprKind::Num(n) => write!(f, "{n}"),
+            ExprKind::Quantity(n, unit) => write!(f, "{n} {unit}"),
+            ExprKind::Convert(e, unit) => write!(f, "(to {e} {unit})"),
+            ExprKind::Parenthed(a) => write!(f, "({a})"),
+            ExprKind::Array(elements) => {
+                write!(f, "[")?;
+                for (i, e) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{e}")?;
+                }
+                write!(f, "]")
+            }
+            ExprKind::Operation(op, e) => {
                 write!(f, "({op}")?;
                 for expr in e {
                     write!(f, " {expr}")?;
                 }
                 write!(f, ")")
             }
+            ExprKind::Call(name, args) => {
+                write!(f, "{name}(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::If(cond, then, otherwise) => write!(f, "(if {cond} {then} {otherwise})"),
         }
     }
 }
 
+/// Whether `args` start with an equation, which makes a call to `solve`
+/// a numeric solve rather than the matrix one.
+pub fn is_equation(args: &[Expression]) -> bool {
+    matches!(args.first(), Some(Expression { kind: ExprKind::Operation(op, _), .. }) if op == "=")
+}
+
+/// Whether `source` has spaces outside brackets, as `a + b` and `3 m`
+/// have but `(a + b)` and `f(a, b)` don't.
+fn spaced(source: &str) -> bool {
+    let mut depth = 0;
+    source.chars().any(|c| {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+        c == ' ' && depth == 0
+    })
+}
+
+/// `name = expr`, or `name(params) = expr` for a function definition.
 #[derive(Debug, Clone)]
-pub struct Assign(pub String, pub Expression);
+pub struct Assign {
+    pub name: String,
+    pub params: Vec<String>,
+    pub expr: Expression,
+    pub span: Span,
+    /// Comments from the lines before the assignment up to the end of its
+    /// own line.
+    pub comments: Vec<Comment>,
+}
+
+impl Assign {
+    /// The assignment written back as source, `f(x) = x + 1`.
+    pub fn to_source(&self) -> String {
+        let shadow = if constant(&self.name).is_some() { "let " } else { "" };
+        match self.params.is_empty() {
+            true => format!("{shadow}{} = {}", self.name, self.expr.to_source()),
+            false => format!("{shadow}{}({}) = {}", self.name, self.params.join(", "), self.expr.to_source()),
+        }
+    }
+
+    /// The names the assigned value depends on: those its expression
+    /// refers to, except a function's own parameters.
+    pub fn dependencies(&self) -> Vec<String> {
+        let mut names = self.expr.names();
+        names.retain(|n| !self.params.contains(n));
+        names
+    }
+}
 
 pub struct Parser {
     tokens: Vec<Token>,
@@ -41,16 +251,30 @@ pub struct Parser {
 
 pub type ParseResult<T> = Result<T, ParsingError>;
 
+/// How a token is named in error messages.
+fn describe(t: &TokenType) -> String {
+    match t {
+        TokenType::EndExpr => t.to_string(),
+        t => format!("`{t}`"),
+    }
+}
+
+/// A unit literal whose exponents don't fit, like `N^2000000000`, whose
+/// seconds are to the power -4000000000.
+fn too_large(Overflow(name, exp): Overflow) -> ParsingError {
+    ParsingError::InvalidExpression(format!("`{name}^{exp}` is too large a power for a unit"))
+}
+
 impl Parser {
-    pub fn peek(&self, p: usize) -> Option<&Token> {
+    fn peek(&self, p: usize) -> Option<&Token> {
         self.tokens.get(self.pos + p)
     }
 
-    pub fn peek_type(&self, p: usize) -> Option<&TokenType> {
+    fn peek_type(&self, p: usize) -> Option<&TokenType> {
         self.tokens.get(self.pos + p).map(|t| &t.token_type)
     }
 
-    pub fn next(&mut self) -> Option<Token> {
+    fn next(&mut self) -> Option<Token> {
         if self.pos >= self.tokens.len() {
             return None;
         }
@@ -60,17 +284,33 @@ impl Parser {
         Some(t)
     }
 
-    pub fn expect(&mut self, expected: TokenType) -> ParseResult<()> {
+    fn expect(&mut self, expected: TokenType) -> ParseResult<Token> {
         match self.peek(0) {
-            Some(token) if token.token_type == expected => {
-                self.next();
-                Ok(())
+            Some(token) if token.token_type == expected => Ok(self.next().unwrap()),
+            _ => Err(self.expected(&format!("`{expected}`"), self.peek(0).cloned())),
+        }
+    }
+
+    /// Error for finding `found` (or nothing) where `expected` should be.
+    fn expected(&self, expected: &str, found: Option<Token>) -> ParsingError {
+        match found {
+            Some(token) => {
+                ParsingError::Expected(expected.to_string(), describe(&token.token_type)).at(token.span)
             }
-            Some(token) => Err(ParsingError::Expected(
-                format!("{:?}", expected),
-                format!("{:?}", token.token_type),
-            )),
-            None => Err(ParsingError::UnexpectedEof),
+            None => ParsingError::UnexpectedEof.at(self.eof_span()),
+        }
+    }
+
+    /// Empty span just after the last token.
+    fn eof_span(&self) -> Span {
+        match self.tokens.last() {
+            Some(t) => Span {
+                start: t.span.end,
+                end: t.span.end,
+                line: t.span.line,
+                col: t.span.col + (t.span.end - t.span.start),
+            },
+            None => Span::default(),
         }
     }
 }
@@ -82,6 +322,7 @@ impl Parser {
 
     pub fn parse_program(&mut self) -> ParseResult<Program> {
         let mut buf = vec![];
+        let mut first = self.pos;
         while self.peek(0).is_some() {
             if self.peek_type(0) == Some(&TokenType::EndExpr) {
                 self.next();
@@ -89,13 +330,18 @@ impl Parser {
             }
             
             match self.parse_assign() {
-                Ok(assign) => {
-                    buf.push(assign);
-                    if let Err(e) = self.expect(TokenType::EndExpr) {
-                        if self.peek(0).is_some() {
-                            return Err(e);
-                        }
+                Ok(mut assign) => {
+                    if let Err(e) = self.expect(TokenType::EndExpr)
+                        && self.peek(0).is_some()
+                    {
+                        return Err(e);
                     }
+                    assign.comments = self.tokens[first..self.pos]
+                        .iter()
+                        .flat_map(|t| t.trivia.iter().cloned())
+                        .collect();
+                    first = self.pos;
+                    buf.push(assign);
                 }
                 Err(e) => return Err(e),
             }
@@ -104,98 +350,376 @@ impl Parser {
         Ok(Program { body: buf })
     }
 
+    /// Parses all of the tokens as one expression, like a line typed in
+    /// the REPL.
+    pub fn parse_expression(&mut self) -> ParseResult<Expression> {
+        let expr = self.parse_expr_pratt(0.)?;
+        self.skip_newlines();
+        match self.next() {
+            None => Ok(expr),
+            other => Err(self.expected("an operator", other)),
+        }
+    }
+
+    /// Parses all of the tokens as an equation, `lhs = rhs`, like the one
+    /// given to `solve`.
+    pub fn parse_equation(&mut self) -> ParseResult<(Expression, Expression)> {
+        let lhs = self.parse_expr_pratt(0.)?;
+        self.expect(TokenType::Assign)?;
+        let rhs = self.parse_expression()?;
+        Ok((lhs, rhs))
+    }
+
     pub fn parse_assign(&mut self) -> ParseResult<Assign> {
+        let shadow = match self.peek_type(0) {
+            Some(TokenType::Let) => self.next().map(|t| t.span),
+            _ => None,
+        };
         let id = match self.peek_type(0) {
             Some(TokenType::Ident(a)) => a.clone(),
-            Some(TokenType::EndExpr) => {
+            Some(TokenType::EndExpr) if shadow.is_none() => {
                 self.next();
                 return self.parse_assign();
             }
-            _ => {
-                return Err(ParsingError::Expected(
-                    "any identifier".to_string(),
-                    format!("{:?}", self.peek(0).map(|t| &t.token_type)),
-                ))
+            _ => return Err(self.expected("any identifier", self.peek(0).cloned())),
+        };
+        let start = self.next().unwrap().span;
+        if shadow.is_none() && constant(&id).is_some() {
+            return Err(ParsingError::AssignToConstant(id).at(start));
+        }
+
+        let params = match self.peek_type(0) {
+            Some(TokenType::LParen) => {
+                self.next();
+                self.parse_params()?
             }
+            _ => vec![],
         };
-        self.next();
 
-        self.expect(TokenType::Assign)
-            .map_err(|_| ParsingError::InvalidAssignment)?;
+        if let Err(e) = self.expect(TokenType::Assign) {
+            return Err(ParsingError::InvalidAssignment.at(e.span().unwrap_or(start)));
+        }
 
         let expr = self.parse_expr_pratt(0.)?;
 
-        Ok(Assign(id, expr))
+        Ok(Assign {
+            name: id,
+            params,
+            span: shadow.unwrap_or(start).to(expr.span),
+            expr,
+            comments: vec![],
+        })
+    }
+
+    /// Parses a function definition's parameter names, after its opening
+    /// parenthesis.
+    fn parse_params(&mut self) -> ParseResult<Vec<String>> {
+        let mut params: Vec<String> = vec![];
+        loop {
+            match self.next() {
+                Some(Token {
+                    token_type: TokenType::Ident(p),
+                    span,
+                    ..
+                }) => {
+                    if params.contains(&p) {
+                        return Err(ParsingError::Unexpected(format!("repeated parameter {p}")).at(span));
+                    }
+                    params.push(p);
+                }
+                other => return Err(self.expected("parameter name", other)),
+            }
+            match self.next() {
+                Some(Token {
+                    token_type: TokenType::Comma,
+                    ..
+                }) => continue,
+                Some(Token {
+                    token_type: TokenType::RParen,
+                    ..
+                }) => return Ok(params),
+                other => return Err(self.expected("`,` or `)`", other)),
+            }
+        }
     }
 
     pub fn parse_expr_pratt(&mut self, min_bp: f32) -> ParseResult<Expression> {
         let mut lhs = match self.next() {
             Some(Token {
                 token_type: TokenType::Number(n),
+                span,
+                ..
+            }) => match self.peek_type(0) {
+                Some(TokenType::Ident(u)) if Unit::named(u).is_some() && self.peek_type(1) != Some(&TokenType::LParen) => {
+                    let (unit, end) = self.parse_unit()?;
+                    Expression::new(ExprKind::Quantity(n, unit), span.to(end))
+                }
+                _ => Expression::new(ExprKind::Num(n), span),
+            },
+            Some(Token {
+                token_type: TokenType::Ident(i),
+                span,
                 ..
-            }) => Expression::Num(n),
+            }) if self.peek_type(0) == Some(&TokenType::LParen) => {
+                self.next();
+                let (args, end) = self.parse_args()?;
+                Expression::new(ExprKind::Call(i, args), span.to(end))
+            }
             Some(Token {
                 token_type: TokenType::Ident(i),
+                span,
                 ..
-            }) => Expression::Var(i),
+            }) => Expression::new(ExprKind::Var(i), span),
 
             Some(Token {
                 token_type: TokenType::LParen,
+                span,
                 ..
             }) => {
                 let expr = self.parse_expr_pratt(0.)?;
                 match self.next() {
                     Some(Token {
                         token_type: TokenType::RParen,
+                        span: end,
                         ..
-                    }) => Expression::Parenthed(Box::new(expr)),
-                    other => {
-                        return Err(ParsingError::Expected(
-                            ")".to_string(),
-                            format!("{:?}", other.map(|t| t.token_type)),
-                        ))
-                    }
+                    }) => Expression::new(ExprKind::Parenthed(Box::new(expr)), span.to(end)),
+                    other => return Err(self.expected("`)`", other)),
                 }
             }
 
+            Some(Token {
+                token_type: TokenType::LBracket,
+                span,
+                ..
+            }) => {
+                let (elements, end) = self.parse_elements()?;
+                Expression::new(ExprKind::Array(elements), span.to(end))
+            }
+
+            Some(Token {
+                token_type: TokenType::If,
+                span,
+                ..
+            }) => {
+                let cond = self.parse_expr_pratt(0.)?;
+                self.expect(TokenType::Then)?;
+                let then = self.parse_expr_pratt(0.)?;
+                self.expect(TokenType::Else)?;
+                let otherwise = self.parse_expr_pratt(0.)?;
+                let span = span.to(otherwise.span);
+                Expression::new(
+                    ExprKind::If(Box::new(cond), Box::new(then), Box::new(otherwise)),
+                    span,
+                )
+            }
+
             Some(Token {
                 token_type: TokenType::Op(op),
+                span,
                 ..
             }) if is_valid_unary(op.as_str()) => {
                 let (_, bp_r) = unary_binding_power(&op);
                 let rhs = self.parse_expr_pratt(bp_r)?;
-                Expression::Operation(op.clone(), vec![rhs])
+                let span = span.to(rhs.span);
+                Expression::new(ExprKind::Operation(op.clone(), vec![rhs]), span)
             }
             Some(token) => {
-                return Err(ParsingError::InvalidExpression(format!(
-                    "Unexpected token: {:?}",
-                    token.token_type
-                )))
+                return Err(ParsingError::Unexpected(describe(&token.token_type))
+                .at(token.span))
             }
-            None => return Err(ParsingError::UnexpectedEof),
+            None => return Err(ParsingError::UnexpectedEof.at(self.eof_span())),
         };
 
         loop {
-            let op = match self.peek_type(0) {
-                None | Some(TokenType::EndExpr) | Some(TokenType::RParen) => break,
-                Some(TokenType::Op(op)) => op.clone(),
-                Some(t) => {
-                    return Err(ParsingError::InvalidExpression(format!(
-                        "expecting an operator, but found: {t}"
-                    )))
+            // `to` converts everything before it, so it binds loosest
+            if let Some(TokenType::Ident(to)) = self.peek_type(0)
+                && to == "to"
+            {
+                if min_bp > 0. {
+                    break;
                 }
+                self.next();
+                let (unit, end) = self.parse_unit()?;
+                let span = lhs.span.to(end);
+                lhs = Expression::new(ExprKind::Convert(Box::new(lhs), unit), span);
+                continue;
+            }
+
+            let (op, (bp_l, bp_r)) = match self.peek(0) {
+                None => break,
+                Some(t) => match &t.token_type {
+                    TokenType::EndExpr
+                    | TokenType::RParen
+                    | TokenType::RBracket
+                    | TokenType::Comma
+                    | TokenType::Assign
+                    | TokenType::Then
+                    | TokenType::Else => break,
+                    TokenType::Op(op) => match binding_power(op) {
+                        Some(bp) => (op.clone(), bp),
+                        None => return Err(ParsingError::Unexpected(describe(&t.token_type)).at(t.span)),
+                    },
+                    TokenType::Number(_)
+                    | TokenType::Ident(_)
+                    | TokenType::LParen
+                    | TokenType::LBracket
+                    | TokenType::If => {
+                        let before = &self.tokens[self.pos - 1];
+                        return Err(ParsingError::MissingOperator(
+                            describe(&before.token_type),
+                            describe(&t.token_type),
+                        )
+                        .at(before.span.to(t.span)))
+                    }
+                    tt => {
+                        return Err(ParsingError::InvalidExpression(format!(
+                            "expecting an operator, but found: {tt}"
+                        ))
+                        .at(t.span))
+                    }
+                },
             };
-            
-            let (bp_l, bp_r) = binding_power(op.as_str());
+
             if bp_l < min_bp {
                 break;
             }
             
             self.next();
             let rhs = self.parse_expr_pratt(bp_r)?;
-            lhs = Expression::Operation(op.to_owned(), vec![lhs, rhs]);
+            let span = lhs.span.to(rhs.span);
+            lhs = Expression::new(ExprKind::Operation(op.to_owned(), vec![lhs, rhs]), span);
         }
 
         Ok(lhs)
     }
+
+    /// Parses a unit like `km` or `kg*m/s^2`, and gives its span. Only
+    /// the first name may have spaces before it, so `3 m / s` divides
+    /// `3 m` by a variable `s`.
+    fn parse_unit(&mut self) -> ParseResult<(Unit, Span)> {
+        let mut unit = Unit::default();
+        let mut divide = false;
+        let mut span: Option<Span> = None;
+        loop {
+            let (mut factor, mut end) = match self.next() {
+                Some(Token {
+                    token_type: TokenType::Ident(name),
+                    span,
+                    ..
+                }) if Unit::named(&name).is_some() => (Unit::named(&name).unwrap(), span),
+                other => return Err(self.expected("a unit", other)),
+            };
+            if self.attached(end, &TokenType::Op("^".to_string())) {
+                self.next();
+                let (exp, exp_end) = self.parse_unit_exponent()?;
+                factor = factor.powi(exp).map_err(|o| too_large(o).at(end.to(exp_end)))?;
+                end = exp_end;
+            }
+            let whole = span.map_or(end, |s| s.to(end));
+            let product = if divide { unit.div(&factor) } else { unit.mul(&factor) };
+            unit = product.map_err(|o| too_large(o).at(whole))?;
+            span = Some(whole);
+
+            // a `*` or `/` only continues the unit when written between two names
+            let op = match (self.peek(0), self.peek(1)) {
+                (
+                    Some(Token { token_type: TokenType::Op(op), span: op_span, .. }),
+                    Some(Token { token_type: TokenType::Ident(_), span: name_span, .. }),
+                ) if (op == "*" || op == "/") && op_span.start == whole.end && name_span.start == op_span.end => op.clone(),
+                _ => return Ok((unit, whole)),
+            };
+            self.next();
+            divide = op == "/";
+        }
+    }
+
+    /// The whole exponent after a unit's `^`, as in `s^-2`.
+    fn parse_unit_exponent(&mut self) -> ParseResult<(i32, Span)> {
+        let negative = match self.peek_type(0) {
+            Some(TokenType::Op(op)) if op == "-" => self.next().is_some(),
+            _ => false,
+        };
+        match self.next() {
+            Some(Token {
+                token_type: TokenType::Number(n),
+                span,
+                ..
+            }) if !n.imaginary && n.text.parse::<i32>().is_ok() => {
+                let exp: i32 = n.text.parse().unwrap();
+                Ok((if negative { -exp } else { exp }, span))
+            }
+            other => Err(self.expected("a whole exponent", other)),
+        }
+    }
+
+    /// Whether the next token is `expected`, written right after `prev`
+    /// without spaces.
+    fn attached(&self, prev: Span, expected: &TokenType) -> bool {
+        self.peek(0).is_some_and(|t| &t.token_type == expected && t.span.start == prev.end)
+    }
+
+    /// Parses the elements of an array after its `[`, and gives the `]`'s
+    /// span. Rows may be split over several lines.
+    fn parse_elements(&mut self) -> ParseResult<(Vec<Expression>, Span)> {
+        let mut elements = vec![];
+        loop {
+            self.skip_newlines();
+            elements.push(self.parse_expr_pratt(0.)?);
+            self.skip_newlines();
+            match self.next() {
+                Some(Token {
+                    token_type: TokenType::Comma,
+                    ..
+                }) => continue,
+                Some(Token {
+                    token_type: TokenType::RBracket,
+                    span,
+                    ..
+                }) => return Ok((elements, span)),
+                other => return Err(self.expected("`,` or `]`", other)),
+            }
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_type(0) == Some(&TokenType::EndExpr) {
+            self.next();
+        }
+    }
+
+    /// Parses a call's argument list, after its opening parenthesis.
+    /// Also returns the closing parenthesis' span.
+    fn parse_args(&mut self) -> ParseResult<(Vec<Expression>, Span)> {
+        let mut args = vec![];
+        if self.peek_type(0) == Some(&TokenType::RParen) {
+            return Ok((args, self.next().unwrap().span));
+        }
+
+        loop {
+            let arg = self.parse_expr_pratt(0.)?;
+            // an equation, `solve(pi * r^2 = 10, r)`
+            let arg = match self.peek_type(0) {
+                Some(TokenType::Assign) => {
+                    self.next();
+                    let rhs = self.parse_expr_pratt(0.)?;
+                    let span = arg.span.to(rhs.span);
+                    Expression::new(ExprKind::Operation("=".to_string(), vec![arg, rhs]), span)
+                }
+                _ => arg,
+            };
+            args.push(arg);
+            match self.next() {
+                Some(Token {
+                    token_type: TokenType::Comma,
+                    ..
+                }) => continue,
+                Some(Token {
+                    token_type: TokenType::RParen,
+                    span,
+                    ..
+                }) => return Ok((args, span)),
+                other => return Err(self.expected("`,` or `)`", other)),
+            }
+        }
+    }
 }
@@ -0,0 +1,596 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{Add, Div, Mul, Neg, Rem, Sub},
+};
+
+/// A signed integer of any size. The magnitude is stored as base 2^32
+/// limbs, least significant first, without trailing zero limbs, so zero
+/// has no limbs at all and every value has exactly one representation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BigInt {
+    negative: bool,
+    mag: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_mag(negative: bool, mut mag: Vec<u32>) -> Self {
+        trim(&mut mag);
+        Self {
+            negative: negative && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    pub fn zero() -> Self {
+        Self::default()
+    }
+
+    pub fn one() -> Self {
+        Self::from(1u64)
+    }
+
+    /// Parses unsigned `digits` in `radix`, e.g. `("ff", 16)`.
+    pub fn from_digits(digits: &str, radix: u32) -> Option<Self> {
+        if digits.is_empty() {
+            return None;
+        }
+        let mut mag = vec![];
+        for c in digits.chars() {
+            mag = mul_small_add(&mag, radix, c.to_digit(radix)?);
+        }
+        Some(Self::from_mag(false, mag))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_one(&self) -> bool {
+        !self.negative && self.mag == [1]
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.mag.first().is_none_or(|l| l % 2 == 0)
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::from_mag(false, self.mag.clone())
+    }
+
+    /// How many bits the magnitude needs.
+    pub fn bits(&self) -> u64 {
+        bits(&self.mag)
+    }
+
+    /// The magnitude shifted left by `n` bits, keeping the sign.
+    pub fn shl(&self, n: u64) -> Self {
+        Self::from_mag(self.negative, shl_mag(&self.mag, n))
+    }
+
+    /// Quotient and remainder of truncating division, like Rust's `/` and
+    /// `%` on primitive integers. Panics when `other` is zero.
+    pub fn div_rem(&self, other: &Self) -> (Self, Self) {
+        assert!(!other.is_zero(), "BigInt division by zero");
+        let (q, r) = divrem_mag(&self.mag, &other.mag);
+        (
+            Self::from_mag(self.negative != other.negative, q),
+            Self::from_mag(self.negative, r),
+        )
+    }
+
+    /// Quotient rounded towards negative infinity.
+    pub fn div_floor(&self, other: &Self) -> Self {
+        let (q, r) = self.div_rem(other);
+        if !r.is_zero() && r.negative != other.negative {
+            q - Self::one()
+        } else {
+            q
+        }
+    }
+
+    pub fn gcd(&self, other: &Self) -> Self {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let r = a.div_rem(&b).1;
+            a = b;
+            b = r;
+        }
+        a
+    }
+
+    pub fn pow(&self, mut exp: u32) -> Self {
+        let mut base = self.clone();
+        let mut acc = Self::one();
+        while exp > 0 {
+            if exp % 2 == 1 {
+                acc = &acc * &base;
+            }
+            base = &base * &base;
+            exp /= 2;
+        }
+        acc
+    }
+
+    /// Largest integer whose square is at most `self`, or `None` for
+    /// negative numbers.
+    pub fn isqrt(&self) -> Option<Self> {
+        if self.negative {
+            return None;
+        }
+        if self.is_zero() {
+            return Some(Self::zero());
+        }
+        // Newton's method from above converges monotonically
+        let mut x = Self::one().shl(self.bits() / 2 + 1);
+        loop {
+            let y = (&x + &(self / &x)).div_rem(&Self::from(2u64)).0;
+            if y >= x {
+                return Some(x);
+            }
+            x = y;
+        }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let m = self.mag.iter().rev().fold(0u64, |acc, &l| (acc << 32) | l as u64);
+        match self.negative {
+            true if m <= i64::MAX as u64 + 1 => Some((m as i64).wrapping_neg()),
+            false if m <= i64::MAX as u64 => Some(m as i64),
+            _ => None,
+        }
+    }
+
+    /// The nearest `f64`, or an infinity when it's too large.
+    pub fn to_f64(&self) -> f64 {
+        let bits = self.bits();
+        let magnitude = if bits <= 64 {
+            self.mag.iter().rev().fold(0u64, |acc, &l| (acc << 32) | l as u64) as f64
+        } else {
+            // keep the top 64 bits; the cast rounds them to 53
+            let top = shr_mag(&self.mag, bits - 64);
+            let top = top.iter().rev().fold(0u64, |acc, &l| (acc << 32) | l as u64);
+            ldexp(top as f64, (bits - 64) as i64)
+        };
+        if self.negative { -magnitude } else { magnitude }
+    }
+}
+
+/// `x * 2^exp`, without overflowing the intermediate power of two.
+pub fn ldexp(mut x: f64, mut exp: i64) -> f64 {
+    while exp > 1000 {
+        x *= 2f64.powi(1000);
+        exp -= 1000;
+    }
+    while exp < -1000 {
+        x *= 2f64.powi(-1000);
+        exp += 1000;
+    }
+    x * 2f64.powi(exp as i32)
+}
+
+impl From<u64> for BigInt {
+    fn from(n: u64) -> Self {
+        Self::from_mag(false, vec![n as u32, (n >> 32) as u32])
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let mut b = Self::from(n.unsigned_abs());
+        b.negative = n < 0;
+        b
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // peel off 9 decimal digits at a time
+        let mut chunks = vec![];
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (q, r) = divrem_small(&mag, 1_000_000_000);
+            chunks.push(r);
+            mag = q;
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{chunk:09}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Neg for BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        Self::from_mag(!self.negative, self.mag)
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_mag(self.negative, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::from_mag(other.negative, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::from_mag(self.negative, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other.clone()
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_mag(self.negative != other.negative, mul_mag(&self.mag, &other.mag))
+    }
+}
+
+impl Div for &BigInt {
+    type Output = BigInt;
+
+    fn div(self, other: &BigInt) -> BigInt {
+        self.div_rem(other).0
+    }
+}
+
+impl Rem for &BigInt {
+    type Output = BigInt;
+
+    fn rem(self, other: &BigInt) -> BigInt {
+        self.div_rem(other).1
+    }
+}
+
+/// Implements an operator on owned values in terms of the one on references.
+macro_rules! forward_owned {
+    ($($trait:ident $method:ident),*) => {$(
+        impl $trait for BigInt {
+            type Output = BigInt;
+
+            fn $method(self, other: BigInt) -> BigInt {
+                (&self).$method(&other)
+            }
+        }
+    )*};
+}
+
+forward_owned!(Add add, Sub sub, Mul mul, Div div, Rem rem);
+
+// Helpers on magnitudes: little-endian limb slices without sign.
+
+fn trim(mag: &mut Vec<u32>) {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+}
+
+fn bits(mag: &[u32]) -> u64 {
+    match mag.last() {
+        Some(top) => (mag.len() as u64 - 1) * 32 + (32 - top.leading_zeros()) as u64,
+        None => 0,
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &x) in long.iter().enumerate() {
+        let sum = x as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        out.push(carry as u32);
+    }
+    out
+}
+
+/// `a - b`, for `a >= b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let mut diff = x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        out.push(diff as u32);
+    }
+    trim(&mut out);
+    out
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return vec![];
+    }
+    let mut out = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let t = x as u64 * y as u64 + out[i + j] as u64 + carry;
+            out[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    trim(&mut out);
+    out
+}
+
+/// `a * m + add`.
+fn mul_small_add(a: &[u32], m: u32, add: u32) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len() + 1);
+    let mut carry = add as u64;
+    for &x in a {
+        let t = x as u64 * m as u64 + carry;
+        out.push(t as u32);
+        carry = t >> 32;
+    }
+    out.push(carry as u32);
+    trim(&mut out);
+    out
+}
+
+fn divrem_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut out = vec![0; a.len()];
+    let mut rem = 0u64;
+    for i in (0..a.len()).rev() {
+        let cur = (rem << 32) | a[i] as u64;
+        out[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    trim(&mut out);
+    (out, rem as u32)
+}
+
+fn shl_mag(a: &[u32], n: u64) -> Vec<u32> {
+    if a.is_empty() {
+        return vec![];
+    }
+    let (limbs, shift) = ((n / 32) as usize, (n % 32) as u32);
+    let mut out = vec![0; limbs];
+    let mut carry = 0u32;
+    for &x in a {
+        out.push((x << shift) | carry);
+        carry = if shift == 0 { 0 } else { x >> (32 - shift) };
+    }
+    out.push(carry);
+    trim(&mut out);
+    out
+}
+
+fn shr_mag(a: &[u32], n: u64) -> Vec<u32> {
+    let (limbs, shift) = ((n / 32) as usize, (n % 32) as u32);
+    if limbs >= a.len() {
+        return vec![];
+    }
+    let a = &a[limbs..];
+    let mut out = Vec::with_capacity(a.len());
+    for (i, &x) in a.iter().enumerate() {
+        let high = match (shift, a.get(i + 1)) {
+            (0, _) | (_, None) => 0,
+            (_, Some(&next)) => next << (32 - shift),
+        };
+        out.push((x >> shift) | high);
+    }
+    trim(&mut out);
+    out
+}
+
+/// Schoolbook long division on limbs (Knuth's algorithm D).
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(a, b) == Ordering::Less {
+        return (vec![], a.to_vec());
+    }
+    if b.len() == 1 {
+        let (q, r) = divrem_small(a, b[0]);
+        return (q, if r == 0 { vec![] } else { vec![r] });
+    }
+
+    // normalize so the divisor's top limb has its high bit set, which
+    // keeps each estimated quotient limb at most 2 too large
+    let shift = b.last().unwrap().leading_zeros() as u64;
+    let b = shl_mag(b, shift);
+    let mut u = shl_mag(a, shift);
+    u.resize(a.len() + 1, 0);
+    let n = b.len();
+    let (top, second) = (b[n - 1] as u64, b[n - 2] as u64);
+
+    let mut q = vec![0u32; u.len() - n];
+    for j in (0..q.len()).rev() {
+        let num = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+        let (mut qhat, mut rhat) = (num / top, num % top);
+        while qhat >> 32 != 0 || qhat * second > ((rhat << 32) | u[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += top;
+            if rhat >> 32 != 0 {
+                break;
+            }
+        }
+
+        // u[j..=j+n] -= qhat * b
+        let (mut borrow, mut carry) = (0i64, 0u64);
+        for i in 0..n {
+            let p = qhat * b[i] as u64 + carry;
+            carry = p >> 32;
+            let t = u[i + j] as i64 - borrow - (p & 0xffff_ffff) as i64;
+            u[i + j] = t as u32;
+            borrow = (t < 0) as i64;
+        }
+        let t = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = t as u32;
+
+        if t < 0 {
+            // qhat was one too large: add the divisor back
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u[i + j] as u64 + b[i] as u64 + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+        q[j] = qhat as u32;
+    }
+    trim(&mut q);
+    (q, shr_mag(&u[..n], shift))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(text: &str) -> BigInt {
+        match text.strip_prefix('-') {
+            Some(digits) => -BigInt::from_digits(digits, 10).unwrap(),
+            None => BigInt::from_digits(text, 10).unwrap(),
+        }
+    }
+
+    fn limbs(mag: &[u32]) -> BigInt {
+        BigInt::from_mag(false, mag.to_vec())
+    }
+
+    #[test]
+    fn multi_limb_division_adds_back_an_overestimated_digit() {
+        // the first estimate of the quotient digit passes the two-limb test
+        // but is still one too large, so the divisor is added back
+        let a = limbs(&[0, 0, 0x8000_0000, 0x7fff_ffff]);
+        let b = limbs(&[1, 0, 0x8000_0000]);
+        let (q, r) = a.div_rem(&b);
+        assert_eq!(q.to_string(), "4294967294");
+        assert_eq!(r.to_string(), "39614081257132168792477007874");
+    }
+
+    #[test]
+    fn multi_limb_division_lowers_a_too_large_estimate() {
+        // the high digit is added back, and the estimate of the low one is
+        // 2^32, which doesn't fit a limb and is lowered before subtracting
+        let a = limbs(&[0, 0xfffe, 0, 0x8000]);
+        let b = limbs(&[0xffff, 0, 0x8000]);
+        let (q, r) = a.div_rem(&b);
+        assert_eq!(q.to_string(), "4294967295");
+        assert_eq!(r.to_string(), "604462909807310292451327");
+    }
+
+    #[test]
+    fn division_reconstructs_the_dividend() {
+        let a = int("123456789012345678901234567890123456789012345678901234567890");
+        for b in ["98765432109876543210987654321", "-4294967297", "18446744073709551616", "7"] {
+            let b = int(b);
+            let (q, r) = a.div_rem(&b);
+            assert_eq!(&(&q * &b) + &r, a);
+            assert!(r.abs() < b.abs());
+        }
+    }
+
+    #[test]
+    fn division_truncates_and_div_floor_rounds_down() {
+        let (q, r) = int("-7").div_rem(&int("2"));
+        assert_eq!((q.to_string(), r.to_string()), ("-3".to_string(), "-1".to_string()));
+        assert_eq!(int("-7").div_floor(&int("2")).to_string(), "-4");
+        assert_eq!(int("7").div_floor(&int("-2")).to_string(), "-4");
+        assert_eq!(int("-8").div_floor(&int("2")).to_string(), "-4");
+        assert_eq!(int("-36893488147419103232").div_floor(&int("36893488147419103231")).to_string(), "-2");
+    }
+
+    #[test]
+    fn gcd_ignores_signs() {
+        assert_eq!(int("-12").gcd(&int("18")).to_string(), "6");
+        assert_eq!(int("0").gcd(&int("-5")).to_string(), "5");
+        let a = int("340282366920938463463374607431768211456"); // 2^128
+        assert_eq!(a.gcd(&int("-1099511627776")).to_string(), "1099511627776");
+    }
+
+    #[test]
+    fn negative_zero_is_zero() {
+        let zero = -BigInt::zero();
+        assert!(!zero.is_negative());
+        assert_eq!(zero, BigInt::zero());
+        assert_eq!(&int("5") - &int("5"), BigInt::zero());
+    }
+
+    #[test]
+    fn parses_and_prints_other_radixes() {
+        assert_eq!(BigInt::from_digits("ffffffffffffffffffff", 16).unwrap().to_string(), "1208925819614629174706175");
+        assert_eq!(BigInt::from_digits("101", 2).unwrap().to_string(), "5");
+        assert_eq!(BigInt::from_digits("19", 8), None);
+        assert_eq!(BigInt::from_digits("", 10), None);
+    }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        assert_eq!(int("99").isqrt().unwrap().to_string(), "9");
+        assert_eq!(int("100").isqrt().unwrap().to_string(), "10");
+        assert_eq!(int("-1").isqrt(), None);
+        let square = &int("12345678901234567890") * &int("12345678901234567890");
+        assert_eq!((&square - &BigInt::one()).isqrt().unwrap().to_string(), "12345678901234567889");
+    }
+
+    #[test]
+    fn to_f64_rounds_to_nearest() {
+        // 2^53 + 1 is halfway between two floats
+        assert_eq!(int("9007199254740993").to_f64(), 9007199254740992.);
+        assert_eq!(int("-18446744073709551617").to_f64(), -18446744073709551616.);
+        assert_eq!(BigInt::one().shl(2000).to_f64(), f64::INFINITY);
+    }
+
+    #[test]
+    fn to_i64_checks_the_range() {
+        assert_eq!(int("-9223372036854775808").to_i64(), Some(i64::MIN));
+        assert_eq!(int("9223372036854775808").to_i64(), None);
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use crate::{eval::Binding, value::Value};
+
+/// One assignment to a name.
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub binding: Binding,
+    /// The source line it was written on, or `None` when it came from Rust
+    /// or is a function parameter.
+    pub line: Option<usize>,
+    /// How many definitions, of any name, came before this one.
+    pub order: usize,
+}
+
+/// The names of a scope in the order they were first defined, each with
+/// every definition it has had, so that "the last variable assigned" is
+/// well defined and tools can show how a name got its value.
+#[derive(Debug, Default)]
+pub struct Bindings {
+    entries: Vec<(String, Vec<Definition>)>,
+    index: HashMap<String, usize>,
+    count: usize,
+}
+
+impl Bindings {
+    pub fn insert(&mut self, name: String, binding: Binding, line: Option<usize>) {
+        let definition = Definition { binding, line, order: self.count };
+        self.count += 1;
+        match self.index.get(&name) {
+            Some(&i) => self.entries[i].1.push(definition),
+            None => {
+                self.index.insert(name.clone(), self.entries.len());
+                self.entries.push((name, vec![definition]));
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Binding> {
+        self.current(name).map(|d| &d.binding)
+    }
+
+    /// The definition `name` has now.
+    pub fn current(&self, name: &str) -> Option<&Definition> {
+        self.history(name).last()
+    }
+
+    /// Every definition of `name`, oldest first; empty when it has none.
+    pub fn history(&self, name: &str) -> &[Definition] {
+        match self.index.get(name) {
+            Some(&i) => &self.entries[i].1,
+            None => &[],
+        }
+    }
+
+    /// Each name with its current definition, in the order the names were
+    /// first defined.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Definition)> {
+        self.entries.iter().map(|(name, history)| (name.as_str(), history.last().expect("names have a definition")))
+    }
+
+    /// The value of the variable assigned most recently, skipping names
+    /// that are functions now.
+    pub fn last_value(&self) -> Option<&Value> {
+        self.iter()
+            .filter_map(|(_, d)| match &d.binding {
+                Binding::Value(v) => Some((d.order, v)),
+                _ => None,
+            })
+            .max_by_key(|(order, _)| *order)
+            .map(|(_, v)| v)
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{ast::Program, compile::QBEParser, error::BuildError};
+
+const RUNTIME: &str = include_str!("../runtime/runtime.c");
+
+pub type BuildResult<T> = Result<T, BuildError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Ssa,
+    Asm,
+    Exe,
+}
+
+impl Emit {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "ssa" => Some(Self::Ssa),
+            "asm" => Some(Self::Asm),
+            "exe" => Some(Self::Exe),
+            _ => None,
+        }
+    }
+
+    /// Default output path for `input`, e.g. `area.math` -> `area.s`.
+    pub fn default_output(self, input: &Path) -> PathBuf {
+        match self {
+            Self::Ssa => input.with_extension("ssa"),
+            Self::Asm => input.with_extension("s"),
+            Self::Exe => input.with_extension(""),
+        }
+    }
+}
+
+/// Lowers `program` to QBE IL and, depending on `emit`, assembles it with
+/// `qbe` and links it against the runtime with `cc`. The tools are
+/// looked up in `PATH` and can be overridden with the `QBE` and `CC`
+/// environment variables.
+pub fn build(program: Program, output: &Path, emit: Emit) -> BuildResult<()> {
+    let mut qbe = QBEParser::new();
+    qbe.write_program(program)?;
+    let ssa = qbe.module().to_string();
+
+    if emit == Emit::Ssa {
+        return write(output, &ssa);
+    }
+
+    let tmp = env::temp_dir().join(format!("simple_math-{}", std::process::id()));
+    fs::create_dir_all(&tmp).map_err(|e| BuildError::Io(tmp.display().to_string(), e.to_string()))?;
+    let result = assemble_and_link(&ssa, &tmp, output, emit);
+    let _ = fs::remove_dir_all(&tmp);
+    result
+}
+
+fn assemble_and_link(ssa: &str, tmp: &Path, output: &Path, emit: Emit) -> BuildResult<()> {
+    let ssa_path = tmp.join("program.ssa");
+    write(&ssa_path, ssa)?;
+
+    let asm_path = match emit {
+        Emit::Asm => output.to_path_buf(),
+        _ => tmp.join("program.s"),
+    };
+    run(Command::new(tool("QBE", "qbe")).arg("-o").arg(&asm_path).arg(&ssa_path))?;
+    if emit == Emit::Asm {
+        return Ok(());
+    }
+
+    let runtime_path = tmp.join("runtime.c");
+    write(&runtime_path, RUNTIME)?;
+    run(Command::new(tool("CC", "cc"))
+        .arg("-o")
+        .arg(output)
+        .arg(&asm_path)
+        .arg(&runtime_path)
+        .arg("-lm"))
+}
+
+fn tool(var: &str, default: &str) -> String {
+    env::var(var).unwrap_or_else(|_| default.to_string())
+}
+
+fn write(path: &Path, content: &str) -> BuildResult<()> {
+    fs::write(path, content).map_err(|e| BuildError::Io(path.display().to_string(), e.to_string()))
+}
+
+fn run(cmd: &mut Command) -> BuildResult<()> {
+    let name = cmd.get_program().to_string_lossy().to_string();
+    let out = cmd
+        .output()
+        .map_err(|e| BuildError::ToolNotFound(name.clone(), e.to_string()))?;
+    if !out.status.success() {
+        return Err(BuildError::ToolFailed(
+            name,
+            out.status.to_string(),
+            String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
@@ -1,8 +1,15 @@
 use std::collections::HashMap;
 
-use qbe::{Block, Function, Instr, Linkage, Module, Type, Value};
+use qbe::{Cmp, Function, Instr, Linkage, Module, Type, Value};
 
-use crate::ast::{Assign, Expression, Program};
+use crate::{
+    ast::{Assign, ExprKind, Expression, Program, is_equation},
+    constants::constant,
+    error::CompileError,
+    eval::builtin,
+};
+
+pub type CompileResult<T> = Result<T, CompileError>;
 
 fn op_name(op: &str) -> &str {
     match op {
@@ -10,20 +17,71 @@ fn op_name(op: &str) -> &str {
         "-" => "sub",
         "/" => "div",
         "*" => "mul",
+        "^" => "pow",
+        "<" => "simple_math_lt",
+        "<=" => "simple_math_le",
+        ">" => "simple_math_gt",
+        ">=" => "simple_math_ge",
         _ => "unknown",
     }
 }
 
+/// libm function behind a builtin. `log(b, x)`, `min` and `max` are
+/// built out of these in `write_call`.
+fn libm_name(name: &str) -> &str {
+    match name {
+        "abs" => "fabs",
+        "ln" | "log" => "log",
+        "min" => "fmin",
+        "max" => "fmax",
+        _ => name,
+    }
+}
+
+/// A source comment as a single-line QBE comment, without its delimiters.
+fn comment_text(comment: &str) -> String {
+    let text = match comment.strip_prefix('#') {
+        Some(line) => line,
+        None => &comment[2..comment.len() - 2],
+    };
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
 fn gen_qbe_var_name(op: &str, sid: usize) -> String {
     format!("{op}_{sid}")
 }
 
 pub struct QBEParser<'lt> {
     op_counts: HashMap<String, usize>,
+    generations: HashMap<String, usize>,
+    arities: HashMap<String, usize>,
+    params: Vec<String>,
+    last_assigned: Option<String>,
     module: Module<'lt>,
 }
 
+impl Default for QBEParser<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'lt> QBEParser<'lt> {
+    pub fn new() -> Self {
+        Self {
+            op_counts: HashMap::new(),
+            generations: HashMap::new(),
+            arities: HashMap::new(),
+            params: vec![],
+            last_assigned: None,
+            module: Module::new(),
+        }
+    }
+
+    pub fn module(&self) -> &Module<'lt> {
+        &self.module
+    }
+
     fn get_count(&mut self, op: &str) -> usize {
         match self.op_counts.get(op) {
             Some(i) => *i,
@@ -43,48 +101,355 @@ impl<'lt> QBEParser<'lt> {
         }
     }
 
-    pub fn write_program(&mut self, tree: Program) {
+    /// Returns a fresh temporary named after `op`, e.g. `%add_3`.
+    fn temporary(&mut self, op: &str) -> Value {
+        let count = self.get_count(op);
+        self.increment_count(op);
+        Value::Temporary(gen_qbe_var_name(op, count))
+    }
+
+    /// Name of the function holding the current definition of `var`.
+    /// Every redefinition of a variable gets a new generation, so earlier
+    /// assignments keep calling the value they saw when they were written.
+    fn current_generation(&self, var: &str) -> Option<String> {
+        self.generations
+            .get(var)
+            .map(|g| gen_qbe_var_name(var, *g))
+    }
+
+    pub fn write_program(&mut self, tree: Program) -> CompileResult<()> {
         self.module = Module::new();
+        self.generations.clear();
+        self.arities.clear();
+        self.last_assigned = None;
         for assign in tree.body {
-            self.write_assign(assign);
+            self.write_assign(assign)?;
         }
+        self.write_main()
     }
 
-    pub fn write_assign(&mut self, a: Assign) {
-        let name = a.0;
-        let mut func = Function::new(Linkage::private(), name.clone(), vec![], Some(Type::Double));
-        let mut block = func.add_block("start");
-        self.write_expr(a.1, block);
-        if name == "result" {
-            /*main function*/
-            return;
+    pub fn write_assign(&mut self, a: Assign) -> CompileResult<()> {
+        let name = a.name;
+        let generation = self.generations.get(&name).map_or(1, |g| g + 1);
+        let arguments = a
+            .params
+            .iter()
+            .map(|p| (Type::Double, Value::Temporary(format!("param_{p}"))))
+            .collect();
+        let mut func = Function::new(
+            Linkage::private(),
+            gen_qbe_var_name(&name, generation),
+            arguments,
+            Some(Type::Double),
+        );
+        let start = func.add_block("start");
+        for comment in &a.comments {
+            start.add_comment(comment_text(&comment.text));
+        }
+
+        // Functions may call themselves, so they are visible to their own
+        // body. A variable's body is lowered before its generation is
+        // bumped, so that `x = x + 1` refers to the previous `x`.
+        if a.params.is_empty() {
+            self.arities.remove(&name);
+            self.params = vec![];
+            let value = self.write_expr(a.expr, &mut func)?;
+            func.add_instr(Instr::Ret(Some(value)));
+            self.generations.insert(name.clone(), generation);
+            self.last_assigned = Some(name);
+        } else {
+            self.generations.insert(name.clone(), generation);
+            self.arities.insert(name, a.params.len());
+            self.params = a.params;
+            let value = self.write_expr(a.expr, &mut func)?;
+            func.add_instr(Instr::Ret(Some(value)));
+            self.params = vec![];
         }
+
+        self.module.add_function(func);
+        Ok(())
     }
 
-    pub fn write_expr(&mut self, e: Expression, block: &mut Block) {
-        match e {
-            Expression::Var(v) => {
-                let count = self.get_count(v.as_str());
-                let name = gen_qbe_var_name(v.as_str(), count).to_string();
+    /// Emits the exported `main`, which prints `result` (or the last
+    /// assigned variable when there is no `result`) through the runtime's
+    /// `simple_math_print` and returns 0.
+    fn write_main(&mut self) -> CompileResult<()> {
+        let mut main = Function::new(Linkage::public(), "main", vec![], Some(Type::Word));
+        main.add_block("start");
 
-                block.assign_instr(
-                    Value::Temporary(name.clone()),
-                    Type::Double,
-                    Instr::Call(name, vec![], None),
-                );
+        let result = match Some("result")
+            .filter(|r| !self.arities.contains_key(*r))
+            .and_then(|r| self.current_generation(r))
+            .or_else(|| self.last_assigned.as_deref().and_then(|v| self.current_generation(v)))
+        {
+            Some(func) => {
+                let temp = Value::Temporary("result".to_string());
+                main.assign_instr(temp.clone(), Type::Double, Instr::Call(func, vec![], None));
+                temp
             }
-            Expression::Num(i) => {}
-            Expression::Parenthed(f) => self.write_expr(*f, block),
-            Expression::Operation(op, exprs) => self.write_operation(op, exprs, block),
+            None => self.write_constant(0., &mut main),
         };
+
+        main.add_instr(Instr::Call(
+            "simple_math_print".to_string(),
+            vec![(Type::Double, result)],
+            None,
+        ));
+        main.add_instr(Instr::Ret(Some(Value::Const(0))));
+        self.module.add_function(main);
+        Ok(())
+    }
+
+    /// QBE has no syntax for double constants in `qbe::Value`, so the bit
+    /// pattern is loaded as a long and reinterpreted with `cast`.
+    fn write_constant(&mut self, n: f64, func: &mut Function<'lt>) -> Value {
+        let temp = self.temporary("num");
+        func.assign_instr(
+            temp.clone(),
+            Type::Double,
+            Instr::Cast(Value::Const(n.abs().to_bits())),
+        );
+        if n.is_sign_negative() {
+            return self.write_negation(temp, func);
+        }
+        temp
+    }
+
+    fn write_negation(&mut self, value: Value, func: &mut Function<'lt>) -> Value {
+        // `qbe::Instr` has no `neg`, and `0 - x` gets the sign of zero
+        // wrong, so compute `-0 - x`. The bits of -0 do not fit the
+        // signed constants QBE parses, hence the shift.
+        let sign_bit = self.temporary("bits");
+        func.assign_instr(sign_bit.clone(), Type::Long, Instr::Shl(Value::Const(1), Value::Const(63)));
+        let neg_zero = self.temporary("num");
+        func.assign_instr(neg_zero.clone(), Type::Double, Instr::Cast(sign_bit));
+        let temp = self.temporary("neg");
+        func.assign_instr(temp.clone(), Type::Double, Instr::Sub(neg_zero, value));
+        temp
+    }
+
+    pub fn write_expr(&mut self, e: Expression, func: &mut Function<'lt>) -> CompileResult<Value> {
+        match e.kind {
+            ExprKind::Var(v) if self.params.contains(&v) => Ok(Value::Temporary(format!("param_{v}"))),
+            ExprKind::Var(v) => {
+                if self.arities.contains_key(&v) {
+                    return Err(CompileError::UndefinedVariable(v));
+                }
+                let Some(name) = self.current_generation(&v) else {
+                    return match constant(&v) {
+                        Some(c) => Ok(self.write_constant(c.value, func)),
+                        None => Err(CompileError::UndefinedVariable(v)),
+                    };
+                };
+                let temp = self.temporary(&v);
+                func.assign_instr(temp.clone(), Type::Double, Instr::Call(name, vec![], None));
+                Ok(temp)
+            }
+            ExprKind::Num(n) if n.imaginary => Err(CompileError::Unsupported("Imaginary numbers".to_string())),
+            ExprKind::Num(n) => Ok(self.write_constant(n.value, func)),
+            ExprKind::Quantity(..) | ExprKind::Convert(..) => Err(CompileError::Unsupported("Units".to_string())),
+            ExprKind::Parenthed(f) => self.write_expr(*f, func),
+            ExprKind::Array(_) => Err(CompileError::Unsupported("Vectors and matrices".to_string())),
+            ExprKind::Operation(op, exprs) => self.write_operation(op, exprs, func),
+            ExprKind::Call(name, args) => self.write_call(name, args, func),
+            ExprKind::If(cond, then, otherwise) => {
+                let cond = self.write_expr(*cond, func)?;
+                let cond = self.write_truth(cond, func);
+                self.write_branches(
+                    cond,
+                    |this, func| this.write_expr(*then, func),
+                    |this, func| this.write_expr(*otherwise, func),
+                    func,
+                )
+            }
+        }
+    }
+
+    /// Lowers a condition to a word that is 1 when `value` is truthy,
+    /// i.e. neither 0 nor NaN.
+    fn write_truth(&mut self, value: Value, func: &mut Function<'lt>) -> Value {
+        let zero = self.write_constant(0., func);
+        let nonzero = self.temporary("cmp");
+        func.assign_instr(nonzero.clone(), Type::Word, Instr::Cmp(Type::Double, Cmp::Ne, value.clone(), zero));
+        let ordered = self.temporary("cmp");
+        func.assign_instr(ordered.clone(), Type::Word, Instr::Cmp(Type::Double, Cmp::Eq, value.clone(), value));
+        let temp = self.temporary("and");
+        func.assign_instr(temp.clone(), Type::Word, Instr::And(nonzero, ordered));
+        temp
+    }
+
+    /// Converts a 0/1 word into the 0/1 double every expression yields.
+    fn write_bool(&mut self, word: Value, func: &mut Function<'lt>) -> Value {
+        let temp = self.temporary("bool");
+        func.assign_instr(temp.clone(), Type::Double, Instr::Swtof(word));
+        temp
+    }
+
+    /// Jumps on the word `cond` to one of two branches and merges their
+    /// values with a phi. Each branch may add blocks of its own, so the phi
+    /// names whichever block each branch ended in.
+    fn write_branches(
+        &mut self,
+        cond: Value,
+        then: impl FnOnce(&mut Self, &mut Function<'lt>) -> CompileResult<Value>,
+        otherwise: impl FnOnce(&mut Self, &mut Function<'lt>) -> CompileResult<Value>,
+        func: &mut Function<'lt>,
+    ) -> CompileResult<Value> {
+        let id = self.get_count("if");
+        self.increment_count("if");
+        let then_label = gen_qbe_var_name("then", id);
+        let else_label = gen_qbe_var_name("else", id);
+        let end_label = gen_qbe_var_name("endif", id);
+
+        func.add_instr(Instr::Jnz(cond, then_label.clone(), else_label.clone()));
+
+        func.add_block(then_label);
+        let then_value = then(self, func)?;
+        let then_end = func.blocks.last().unwrap().label.clone();
+        func.add_instr(Instr::Jmp(end_label.clone()));
+
+        func.add_block(else_label);
+        let else_value = otherwise(self, func)?;
+        let else_end = func.blocks.last().unwrap().label.clone();
+        func.add_instr(Instr::Jmp(end_label.clone()));
+
+        func.add_block(end_label);
+        let temp = self.temporary("if");
+        func.assign_instr(
+            temp.clone(),
+            Type::Double,
+            Instr::Phi(then_end, then_value, else_end, else_value),
+        );
+        Ok(temp)
+    }
+
+    pub fn write_call(
+        &mut self,
+        name: String,
+        args: Vec<Expression>,
+        func: &mut Function<'lt>,
+    ) -> CompileResult<Value> {
+        if name == "diff" && !self.arities.contains_key(&name) {
+            return Err(CompileError::Unsupported("`diff`".to_string()));
+        }
+        if name == "solve" && is_equation(&args) && !self.arities.contains_key(&name) {
+            return Err(CompileError::Unsupported("Equations".to_string()));
+        }
+        let mut values = vec![];
+        for arg in args {
+            values.push(self.write_expr(arg, func)?);
+        }
+
+        if let Some(arity) = self.arities.get(&name) {
+            if *arity != values.len() {
+                return Err(CompileError::InvalidSizeOfArgsFor(name));
+            }
+            let callee = self.current_generation(&name).unwrap();
+            let temp = self.temporary(&name);
+            func.assign_instr(
+                temp.clone(),
+                Type::Double,
+                Instr::Call(callee, values.into_iter().map(|v| (Type::Double, v)).collect(), None),
+            );
+            return Ok(temp);
+        }
+
+        let b = builtin(&name).ok_or_else(|| CompileError::UndefinedFunction(name.clone()))?;
+        if !b.arity.accepts(values.len()) {
+            return Err(CompileError::InvalidSizeOfArgsFor(name));
+        }
+
+        let libm = libm_name(&name).to_string();
+        match name.as_str() {
+            "log" => {
+                let base = self.write_libm_call(&libm, vec![values[0].clone()], func);
+                let x = self.write_libm_call(&libm, vec![values[1].clone()], func);
+                let temp = self.temporary("div");
+                func.assign_instr(temp.clone(), Type::Double, Instr::Div(x, base));
+                Ok(temp)
+            }
+            "min" | "max" => {
+                let mut values = values.into_iter();
+                let first = values.next().unwrap();
+                Ok(values.fold(first, |acc, v| self.write_libm_call(&libm, vec![acc, v], func)))
+            }
+            _ => Ok(self.write_libm_call(&libm, values, func)),
+        }
     }
 
-    pub fn write_operation(&mut self, op: String, exprs: Vec<Expression>, block: &mut Block) {
-        match exprs.len() {
-            2 => {}
-            1 => {}
+    fn write_libm_call(&mut self, name: &str, args: Vec<Value>, func: &mut Function<'lt>) -> Value {
+        let temp = self.temporary(name);
+        func.assign_instr(
+            temp.clone(),
+            Type::Double,
+            Instr::Call(name.to_string(), args.into_iter().map(|a| (Type::Double, a)).collect(), None),
+        );
+        temp
+    }
 
-            _ => panic!("compilador: AAAAAAAAAAAAAAAAAAAAAAAAAa"),
+    pub fn write_operation(
+        &mut self,
+        op: String,
+        exprs: Vec<Expression>,
+        func: &mut Function<'lt>,
+    ) -> CompileResult<Value> {
+        let mut exprs = exprs.into_iter();
+        match (exprs.next(), exprs.next(), exprs.next()) {
+            (Some(lhs), Some(rhs), None) if op == "&&" || op == "||" => {
+                // short-circuits: `rhs` only runs when `lhs` doesn't decide
+                let lhs = self.write_expr(lhs, func)?;
+                let lhs = self.write_truth(lhs, func);
+                let decided = if op == "&&" { 0. } else { 1. };
+                let rest = |this: &mut Self, func: &mut Function<'lt>| {
+                    let rhs = this.write_expr(rhs, func)?;
+                    let rhs = this.write_truth(rhs, func);
+                    Ok(this.write_bool(rhs, func))
+                };
+                let decide = |this: &mut Self, func: &mut Function<'lt>| Ok(this.write_constant(decided, func));
+                if op == "&&" {
+                    self.write_branches(lhs, rest, decide, func)
+                } else {
+                    self.write_branches(lhs, decide, rest, func)
+                }
+            }
+            (Some(lhs), Some(rhs), None) => {
+                let lhs = self.write_expr(lhs, func)?;
+                let rhs = self.write_expr(rhs, func)?;
+                let instr = match op.as_str() {
+                    "+" => Instr::Add(lhs, rhs),
+                    "-" => Instr::Sub(lhs, rhs),
+                    "*" => Instr::Mul(lhs, rhs),
+                    "/" => Instr::Div(lhs, rhs),
+                    "^" | "<" | "<=" | ">" | ">=" => {
+                        return Ok(self.write_libm_call(op_name(&op), vec![lhs, rhs], func));
+                    }
+                    "==" | "!=" => {
+                        let cmp = if op == "==" { Cmp::Eq } else { Cmp::Ne };
+                        let word = self.temporary("cmp");
+                        func.assign_instr(word.clone(), Type::Word, Instr::Cmp(Type::Double, cmp, lhs, rhs));
+                        return Ok(self.write_bool(word, func));
+                    }
+                    _ => return Err(CompileError::UnsupportedOperator(op)),
+                };
+                let temp = self.temporary(op_name(&op));
+                func.assign_instr(temp.clone(), Type::Double, instr);
+                Ok(temp)
+            }
+            (Some(operand), None, None) => {
+                let operand = self.write_expr(operand, func)?;
+                match op.as_str() {
+                    "+" => Ok(operand),
+                    "-" => Ok(self.write_negation(operand, func)),
+                    "!" => {
+                        let truth = self.write_truth(operand, func);
+                        let word = self.temporary("cmp");
+                        func.assign_instr(word.clone(), Type::Word, Instr::Cmp(Type::Word, Cmp::Eq, truth, Value::Const(0)));
+                        Ok(self.write_bool(word, func))
+                    }
+                    _ => Err(CompileError::UnsupportedOperator(op)),
+                }
+            }
+            _ => Err(CompileError::InvalidSizeOfArgsFor(op)),
         }
     }
 }
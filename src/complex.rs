@@ -0,0 +1,317 @@
+use std::{
+    f64::consts::{FRAC_PI_2, LN_10},
+    fmt,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+/// A complex number `re + im*i` in floating point.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+pub const I: Complex = Complex { re: 0., im: 1. };
+
+/// Powers with a whole exponent up to this are done by multiplying, which
+/// keeps results like `(1 + i)^2 == 2i` exact.
+const MAX_MULTIPLIED_POWER: f64 = 64.;
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    pub fn real(re: f64) -> Self {
+        Self { re, im: 0. }
+    }
+
+    pub fn is_real(self) -> bool {
+        self.im == 0.
+    }
+
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// The angle from the positive real axis, in `(-pi, pi]`.
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, 0. - self.im)
+    }
+
+    pub fn exp(self) -> Self {
+        let r = self.re.exp();
+        Self::new(r * self.im.cos(), r * self.im.sin())
+    }
+
+    /// The principal logarithm.
+    pub fn ln(self) -> Self {
+        Self::new(self.abs().ln(), self.arg())
+    }
+
+    /// The principal square root, the one with a non-negative real part.
+    pub fn sqrt(self) -> Self {
+        let r = self.abs();
+        let re = ((r + self.re) / 2.).sqrt();
+        let im = ((r - self.re) / 2.).sqrt();
+        Self::new(re, if self.im < 0. { -im } else { im })
+    }
+
+    pub fn pow(self, exp: Complex) -> Self {
+        if exp.is_real() && exp.re.fract() == 0. && exp.re.abs() <= MAX_MULTIPLIED_POWER {
+            let mut acc = Self::real(1.);
+            for _ in 0..exp.re.abs() as u32 {
+                acc = acc * self;
+            }
+            return if exp.re < 0. { Self::real(1.) / acc } else { acc };
+        }
+        if self == Self::default() {
+            return Self::real(if exp.re > 0. { 0. } else { f64::NAN });
+        }
+        (exp * self.ln()).exp()
+    }
+
+    pub fn sin(self) -> Self {
+        Self::new(self.re.sin() * self.im.cosh(), self.re.cos() * self.im.sinh())
+    }
+
+    pub fn cos(self) -> Self {
+        Self::new(self.re.cos() * self.im.cosh(), -self.re.sin() * self.im.sinh())
+    }
+
+    pub fn sinh(self) -> Self {
+        Self::new(self.re.sinh() * self.im.cos(), self.re.cosh() * self.im.sin())
+    }
+
+    pub fn cosh(self) -> Self {
+        Self::new(self.re.cosh() * self.im.cos(), self.re.sinh() * self.im.sin())
+    }
+
+    /// `asin(z) = -i asinh(iz)`, which puts its cuts on the real axis
+    /// beyond `-1` and `1`.
+    pub fn asin(self) -> Self {
+        self.times_i().asinh().over_i()
+    }
+
+    /// `acos(z) = pi/2 - asin(z)`, with the cuts of `asin`.
+    pub fn acos(self) -> Self {
+        Self::real(FRAC_PI_2) - self.asin()
+    }
+
+    /// `atan(z) = -i atanh(iz)`, with its cuts on the imaginary axis
+    /// beyond `-i` and `i`.
+    pub fn atan(self) -> Self {
+        self.times_i().atanh().over_i()
+    }
+
+    /// The principal `ln(z + sqrt(z^2 + 1))`. It is odd, so the left half
+    /// plane, where the sum would cancel, is done through `-asinh(-z)`.
+    pub fn asinh(self) -> Self {
+        if self.re < 0. {
+            return -(-self).asinh();
+        }
+        (self + (self * self + Self::real(1.)).sqrt()).ln()
+    }
+
+    /// The principal `ln(z + sqrt(z + 1) sqrt(z - 1))`, whose cut is the
+    /// real axis below `1`.
+    pub fn acosh(self) -> Self {
+        (self + (self + Self::real(1.)).sqrt() * (self - Self::real(1.)).sqrt()).ln()
+    }
+
+    /// The principal `(ln(1 + z) - ln(1 - z)) / 2`, whose cuts are the
+    /// real axis beyond `-1` and `1`.
+    pub fn atanh(self) -> Self {
+        let d = (Self::real(1.) + self).ln() - (Self::real(1.) - self).ln();
+        // halving each part keeps `atanh(1)` an infinity with no NaN
+        Self::new(d.re / 2., d.im / 2.)
+    }
+
+    /// `self * i`, without multiplying an infinite part by zero.
+    fn times_i(self) -> Self {
+        Self::new(0. - self.im, self.re)
+    }
+
+    /// `self / i`, without multiplying an infinite part by zero.
+    fn over_i(self) -> Self {
+        Self::new(self.im, 0. - self.re)
+    }
+}
+
+pub type ComplexFn = fn(&[Complex]) -> Complex;
+
+/// The functions that take and give complex numbers, with their arity.
+pub const BUILTINS: &[(&str, usize, ComplexFn)] = &[
+    ("sqrt", 1, |a| a[0].sqrt()),
+    ("exp", 1, |a| a[0].exp()),
+    ("ln", 1, |a| a[0].ln()),
+    ("log10", 1, |a| a[0].ln() / Complex::real(LN_10)),
+    ("log", 2, |a| a[1].ln() / a[0].ln()),
+    ("abs", 1, |a| Complex::real(a[0].abs())),
+    ("arg", 1, |a| Complex::real(a[0].arg())),
+    ("conj", 1, |a| a[0].conj()),
+    ("re", 1, |a| Complex::real(a[0].re)),
+    ("im", 1, |a| Complex::real(a[0].im)),
+    ("sin", 1, |a| a[0].sin()),
+    ("cos", 1, |a| a[0].cos()),
+    ("tan", 1, |a| a[0].sin() / a[0].cos()),
+    ("asin", 1, |a| a[0].asin()),
+    ("acos", 1, |a| a[0].acos()),
+    ("atan", 1, |a| a[0].atan()),
+    ("sinh", 1, |a| a[0].sinh()),
+    ("cosh", 1, |a| a[0].cosh()),
+    ("tanh", 1, |a| a[0].sinh() / a[0].cosh()),
+    ("asinh", 1, |a| a[0].asinh()),
+    ("acosh", 1, |a| a[0].acosh()),
+    ("atanh", 1, |a| a[0].atanh()),
+];
+
+pub fn builtin(name: &str) -> Option<(usize, ComplexFn)> {
+    BUILTINS.iter().find(|b| b.0 == name).map(|b| (b.1, b.2))
+}
+
+/// Polar form, `r ∠ θ°` with the angle in degrees.
+pub struct Polar(pub Complex);
+
+impl fmt::Display for Polar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ∠ {}°", self.0.abs(), self.0.arg().to_degrees())
+    }
+}
+
+/// `a + bi`, leaving out a part that is zero.
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let imaginary = |f: &mut fmt::Formatter<'_>, im: f64| match im {
+            1. => write!(f, "i"),
+            im => write!(f, "{im}i"),
+        };
+        match (self.re, self.im) {
+            (re, 0.) => write!(f, "{re}"),
+            (0., -1.) => write!(f, "-i"),
+            (0., im) => imaginary(f, im),
+            (re, im) if im < 0. => {
+                write!(f, "{re} - ")?;
+                imaginary(f, -im)
+            }
+            (re, im) => {
+                write!(f, "{re} + ")?;
+                imaginary(f, im)
+            }
+        }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let d = other.re * other.re + other.im * other.im;
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / d,
+            (self.im * other.re - self.re * other.im) / d,
+        )
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+
+    /// Subtracts from zero rather than flipping signs, so a zero part
+    /// stays `0` and `ln(-1)` is `pi*i` rather than `-pi*i`.
+    fn neg(self) -> Complex {
+        Complex::new(0. - self.re, 0. - self.im)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_4, PI};
+
+    use super::*;
+
+    fn close(a: Complex, b: Complex) -> bool {
+        (a - b).abs() <= 1e-12 * b.abs().max(1.)
+    }
+
+    #[test]
+    fn inverses_agree_with_the_real_functions_in_their_domain() {
+        for x in [-0.9, -0.5, 0., 0.3, 1.] {
+            assert!(close(Complex::real(x).asin(), Complex::real(x.asin())), "asin({x})");
+            assert!(close(Complex::real(x).acos(), Complex::real(x.acos())), "acos({x})");
+        }
+        for x in [-0.9, 0., 0.5] {
+            assert!(close(Complex::real(x).atanh(), Complex::real(x.atanh())), "atanh({x})");
+        }
+        for x in [-1e10, -3., -0.5, 0., 2., 1e10] {
+            assert!(close(Complex::real(x).atan(), Complex::real(x.atan())), "atan({x})");
+            assert!(close(Complex::real(x).asinh(), Complex::real(x.asinh())), "asinh({x})");
+        }
+        for x in [1., 1.5, 1e10] {
+            assert!(close(Complex::real(x).acosh(), Complex::real(x.acosh())), "acosh({x})");
+        }
+    }
+
+    #[test]
+    fn inverses_undo_their_functions() {
+        let z = Complex::new(0.3, -0.7);
+        assert!(close(z.asin().sin(), z));
+        assert!(close(z.acos().cos(), z));
+        assert!(close(z.atan().sin() / z.atan().cos(), z));
+        assert!(close(z.asinh().sinh(), z));
+        assert!(close(z.acosh().cosh(), z));
+        assert!(close(z.atanh().sinh() / z.atanh().cosh(), z));
+    }
+
+    #[test]
+    fn inverses_take_principal_values_outside_the_real_domain() {
+        let ln = (2f64 + 3f64.sqrt()).ln();
+        assert!(close(Complex::real(2.).asin(), Complex::new(PI / 2., -ln)));
+        assert!(close(Complex::real(-2.).asin(), Complex::new(-PI / 2., ln)));
+        assert!(close(Complex::real(2.).acos(), Complex::new(0., ln)));
+        assert!(close(Complex::real(-2.).acosh(), Complex::new(ln, PI)));
+        assert!(close(Complex::real(2.).atanh(), Complex::new(3f64.ln() / 2., -PI / 2.)));
+        assert!(close(Complex::new(0., 2.).asinh(), Complex::new(ln, PI / 2.)));
+        assert!(close(Complex::new(1., 1.).atan(), Complex::new(1.0172219678978514, 0.4023594781085251)));
+        assert!(close(I.asin(), Complex::new(0., 2f64.sqrt().ln_1p())));
+        assert!(close(Complex::new(0., 1e10).asin(), Complex::new(0., 1e10f64.asinh())));
+        assert!(close(Complex::real(1.).atan(), Complex::real(FRAC_PI_4)));
+    }
+
+    #[test]
+    fn atanh_has_poles_at_one_and_minus_one() {
+        assert_eq!(Complex::real(1.).atanh(), Complex::real(f64::INFINITY));
+        assert_eq!(Complex::real(-1.).atanh(), Complex::real(f64::NEG_INFINITY));
+    }
+}
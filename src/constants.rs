@@ -0,0 +1,43 @@
+use std::f64::consts::{E, PI, TAU};
+
+use crate::decimal::{Context, Decimal};
+
+/// A number programs can use without defining it.
+pub struct Constant {
+    pub name: &'static str,
+    pub value: f64,
+    pub description: &'static str,
+}
+
+/// Looked up when a name has no binding. A program can hide one with
+/// `let pi = 3`, but `pi = 3` alone is an error, as it is most likely a
+/// leftover approximation.
+pub const CONSTANTS: &[Constant] = &[
+    Constant { name: "pi", value: PI, description: "half a turn, in radians" },
+    Constant { name: "tau", value: TAU, description: "a full turn, in radians" },
+    Constant { name: "e", value: E, description: "the base of natural logarithms" },
+    Constant { name: "phi", value: 1.618_033_988_749_895, description: "the golden ratio" },
+    Constant { name: "inf", value: f64::INFINITY, description: "infinity" },
+    Constant { name: "nan", value: f64::NAN, description: "not a number" },
+];
+
+pub fn constant(name: &str) -> Option<&'static Constant> {
+    CONSTANTS.iter().find(|c| c.name == name)
+}
+
+impl Constant {
+    /// The constant to `ctx`'s precision, when it is a finite number.
+    pub fn decimal(&self, ctx: Context) -> Option<Decimal> {
+        // a few more digits, so that only the final rounding shows
+        let wide = Context::new(ctx.precision + 5, ctx.rounding);
+        let two = Decimal::from_int(2);
+        let value = match self.name {
+            "pi" => wide.pi(),
+            "tau" => wide.mul(&two, &wide.pi()),
+            "e" => wide.exp(&Decimal::one())?,
+            "phi" => wide.div(&wide.add(&Decimal::one(), &wide.sqrt(&Decimal::from_int(5))?), &two)?,
+            _ => return None,
+        };
+        Some(ctx.round(value))
+    }
+}
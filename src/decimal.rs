@@ -0,0 +1,593 @@
+use std::{cmp::Ordering, fmt};
+
+use crate::{
+    bigint::BigInt,
+    rational::{MAX_EXACT_EXPONENT, Rational},
+};
+
+/// A decimal floating point number, `coeff * 10^exp`. Values are kept
+/// normalized: the coefficient never ends in a zero digit, and zero has
+/// exponent 0, so equal numbers look the same.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decimal {
+    coeff: BigInt,
+    exp: i64,
+}
+
+/// How a result that doesn't fit the precision loses its last digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// To the nearest, ties to an even last digit (banker's rounding).
+    #[default]
+    HalfEven,
+    /// To the nearest, ties away from zero.
+    HalfUp,
+    /// Towards zero, i.e. truncation.
+    Down,
+}
+
+impl Rounding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "half-even" => Some(Rounding::HalfEven),
+            "half-up" => Some(Rounding::HalfUp),
+            "down" => Some(Rounding::Down),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rounding::HalfEven => write!(f, "half-even"),
+            Rounding::HalfUp => write!(f, "half-up"),
+            Rounding::Down => write!(f, "down"),
+        }
+    }
+}
+
+/// Significant digits kept by every operation, and how they are rounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub precision: u32,
+    pub rounding: Rounding,
+}
+
+/// Arguments of `exp` beyond this many digits before the point overflow
+/// the exponent, so they are left to floating point.
+const MAX_EXP_DIGITS: i64 = 9;
+
+impl Context {
+    pub fn new(precision: u32, rounding: Rounding) -> Self {
+        Self { precision: precision.max(1), rounding }
+    }
+
+    /// The context of a result mixing values from both.
+    pub fn wider(self, other: Context) -> Context {
+        if other.precision > self.precision { other } else { self }
+    }
+
+    /// A few guard digits more, for intermediate results that are
+    /// rounded again at the end.
+    fn working(self, extra: i64) -> Context {
+        Context::new(self.precision + extra.max(0) as u32 + 5, Rounding::HalfEven)
+    }
+
+    pub fn round(self, d: Decimal) -> Decimal {
+        let drop = digit_count(&d.coeff) - self.precision as i64;
+        if drop <= 0 {
+            return d.normalized();
+        }
+        let divisor = pow10(drop);
+        let (mut q, r) = d.coeff.div_rem(&divisor);
+        let twice = (&r.abs() + &r.abs()).cmp(&divisor);
+        let up = match self.rounding {
+            Rounding::Down => false,
+            Rounding::HalfUp => twice != Ordering::Less,
+            Rounding::HalfEven => twice == Ordering::Greater || (twice == Ordering::Equal && !q.is_even()),
+        };
+        if up {
+            let one = BigInt::from(if d.coeff.is_negative() { -1i64 } else { 1 });
+            q = &q + &one;
+        }
+        Decimal { coeff: q, exp: d.exp + drop }.normalized()
+    }
+
+    /// Reads a number literal, rounding it to the precision. `None` when
+    /// its exponent is beyond `MAX_EXACT_EXPONENT`, as later operations
+    /// would build powers of ten that large.
+    pub fn parse(self, text: &str) -> Option<Decimal> {
+        let radix = match text.get(..2) {
+            Some("0x" | "0X") => 16,
+            Some("0b" | "0B") => 2,
+            Some("0o" | "0O") => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            let coeff = BigInt::from_digits(&text[2..], radix)?;
+            return Some(self.round(Decimal { coeff, exp: 0 }));
+        }
+
+        let (mantissa, exp) = match text.find(['e', 'E']) {
+            Some(i) => (&text[..i], text[i + 1..].parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let coeff = BigInt::from_digits(&format!("{int}{frac}"), 10)?;
+        let d = self.round(Decimal { coeff, exp: exp - frac.len() as i64 });
+        (d.adjusted().abs() <= MAX_EXACT_EXPONENT).then_some(d)
+    }
+
+    pub fn rational(self, r: &Rational) -> Decimal {
+        let num = Decimal { coeff: r.numer().clone(), exp: 0 };
+        let den = Decimal { coeff: r.denom().clone(), exp: 0 };
+        self.div(&num, &den).unwrap()
+    }
+
+    pub fn add(self, a: &Decimal, b: &Decimal) -> Decimal {
+        if a.is_zero() || b.is_zero() {
+            return self.round(if a.is_zero() { b.clone() } else { a.clone() });
+        }
+        let (big, small) = if a.adjusted() >= b.adjusted() { (a, b) } else { (b, a) };
+        if big.adjusted() - small.adjusted() > self.precision as i64 + 2 {
+            // far below the last kept digit: only its sign matters for
+            // rounding, so stand in a tiny digit instead of scaling `big`
+            let sticky = Decimal {
+                coeff: BigInt::from(if small.is_negative() { -1i64 } else { 1 }),
+                exp: big.exp.min(big.adjusted() - self.precision as i64) - 1,
+            };
+            return self.round(add_exact(big, &sticky));
+        }
+        self.round(add_exact(a, b))
+    }
+
+    pub fn sub(self, a: &Decimal, b: &Decimal) -> Decimal {
+        self.add(a, &-b.clone())
+    }
+
+    pub fn mul(self, a: &Decimal, b: &Decimal) -> Decimal {
+        self.round(Decimal { coeff: &a.coeff * &b.coeff, exp: a.exp + b.exp })
+    }
+
+    /// `None` when dividing by zero.
+    pub fn div(self, a: &Decimal, b: &Decimal) -> Option<Decimal> {
+        if b.is_zero() {
+            return None;
+        }
+        if a.is_zero() {
+            return Some(Decimal::zero());
+        }
+        let shift = (self.precision as i64 + 2 + digit_count(&b.coeff) - digit_count(&a.coeff)).max(0);
+        let (q, r) = (&a.coeff * &pow10(shift)).div_rem(&b.coeff);
+        Some(self.round(with_sticky(q, a.exp - b.exp - shift, !r.is_zero())))
+    }
+
+    /// `None` for negative numbers.
+    pub fn sqrt(self, a: &Decimal) -> Option<Decimal> {
+        if a.is_negative() {
+            return None;
+        }
+        if a.is_zero() {
+            return Some(Decimal::zero());
+        }
+        // scale to twice the wanted digits, with an even exponent left over
+        let mut shift = (2 * (self.precision as i64 + 2) - digit_count(&a.coeff)).max(0);
+        if (a.exp - shift).rem_euclid(2) != 0 {
+            shift += 1;
+        }
+        let scaled = &a.coeff * &pow10(shift);
+        let root = scaled.isqrt()?;
+        let inexact = &root * &root != scaled;
+        Some(self.round(with_sticky(root, (a.exp - shift).div_euclid(2), inexact)))
+    }
+
+    /// `e^a`, or `None` when it would overflow the exponent.
+    pub fn exp(self, a: &Decimal) -> Option<Decimal> {
+        if a.is_zero() {
+            return Some(Decimal::one());
+        }
+        if a.adjusted() >= MAX_EXP_DIGITS {
+            return None;
+        }
+        // e^a = (e^(a / 2^k))^(2^k), where the series converges quickly;
+        // each squaring doubles the relative error, hence the extra digits
+        let k = (a.to_f64().abs().log2().ceil() as i64 + 1).max(0);
+        let wp = self.working(k / 3 + 1);
+        let reduced = wp.round(Decimal {
+            coeff: &a.coeff * &BigInt::from(5u64).pow(k as u32),
+            exp: a.exp - k,
+        });
+
+        let mut sum = Decimal::one();
+        let mut term = Decimal::one();
+        for n in 1.. {
+            term = wp.div(&wp.mul(&term, &reduced), &Decimal::from_int(n))?;
+            if term.is_zero() || term.adjusted() < sum.adjusted() - wp.precision as i64 - 1 {
+                break;
+            }
+            sum = wp.add(&sum, &term);
+        }
+        for _ in 0..k {
+            sum = wp.mul(&sum, &sum);
+        }
+        Some(self.round(sum))
+    }
+
+    /// Natural logarithm, or `None` for numbers that aren't positive.
+    pub fn ln(self, a: &Decimal) -> Option<Decimal> {
+        if a.is_negative() || a.is_zero() {
+            return None;
+        }
+        let one = Decimal::one();
+        let near_one = self.working(0).sub(a, &one);
+        if near_one.is_zero() {
+            return Some(Decimal::zero());
+        }
+        // the result is about as small as a - 1, and as many digits of it
+        // cancel out in a - e^y
+        let wp = self.working(-near_one.adjusted() + 1);
+
+        // start from a float estimate, even for numbers out of f64's range
+        let lead = Decimal { coeff: a.coeff.clone(), exp: 1 - digit_count(&a.coeff) }.to_f64();
+        let guess = lead.ln() + a.adjusted() as f64 * std::f64::consts::LN_10;
+        let mut y = Decimal::from_f64(guess).unwrap_or_default();
+
+        // Halley's method on e^y = a, which triples the digits each step
+        let two = Decimal::from_int(2);
+        for _ in 0..100 {
+            let e = wp.exp(&y)?;
+            let step = wp.div(&wp.mul(&two, &wp.sub(a, &e)), &wp.add(a, &e))?;
+            y = wp.add(&y, &step);
+            if step.is_zero() || step.adjusted() < y.adjusted() - self.precision as i64 - 2 {
+                break;
+            }
+        }
+        Some(self.round(y))
+    }
+
+    /// π, by the Gauss-Legendre iteration, which doubles the correct
+    /// digits each step.
+    pub fn pi(self) -> Decimal {
+        let wp = self.working(2);
+        let (two, four) = (Decimal::from_int(2), Decimal::from_int(4));
+        let mut a = Decimal::one();
+        let mut b = wp.div(&a, &wp.sqrt(&two).expect("2 is positive")).expect("the root isn't zero");
+        let mut t = wp.div(&a, &four).expect("4 isn't zero");
+        let mut p = Decimal::one();
+        loop {
+            let next = wp.div(&wp.add(&a, &b), &two).expect("2 isn't zero");
+            let diff = wp.sub(&a, &next);
+            if diff.is_zero() || diff.adjusted() < -(wp.precision as i64) {
+                break;
+            }
+            b = wp.sqrt(&wp.mul(&a, &b)).expect("both means are positive");
+            t = wp.sub(&t, &wp.mul(&p, &wp.mul(&diff, &diff)));
+            p = wp.mul(&p, &two);
+            a = next;
+        }
+        let sum = wp.add(&a, &b);
+        self.round(wp.div(&wp.mul(&sum, &sum), &wp.mul(&four, &t)).expect("t is positive"))
+    }
+
+    /// `a^b`; `None` when there is no real result, as for `(-8)^(1/3)`, or
+    /// for zero to a negative power.
+    pub fn pow(self, a: &Decimal, b: &Decimal) -> Option<Decimal> {
+        if let Some(n) = b.to_i64().filter(|n| n.unsigned_abs() < 1 << 20) {
+            let wp = self.working(digit_count(&BigInt::from(n)));
+            let (mut base, mut e, mut acc) = (a.clone(), n.unsigned_abs(), Decimal::one());
+            while e > 0 {
+                if e % 2 == 1 {
+                    acc = wp.mul(&acc, &base);
+                }
+                base = wp.mul(&base, &base);
+                e /= 2;
+            }
+            return match n < 0 {
+                true => self.div(&Decimal::one(), &acc),
+                false => Some(self.round(acc)),
+            };
+        }
+        if a.is_zero() && !b.is_negative() {
+            return Some(Decimal::zero());
+        }
+        // a^b = e^(b ln a): the error of b ln a grows with its size
+        let wp = self.working(b.adjusted().max(0) + 2);
+        let product = wp.mul(b, &wp.ln(a)?);
+        let wp = self.working(product.adjusted().max(0));
+        Some(self.round(wp.exp(&product)?))
+    }
+}
+
+impl Decimal {
+    pub fn zero() -> Self {
+        Self { coeff: BigInt::zero(), exp: 0 }
+    }
+
+    pub fn one() -> Self {
+        Self::from_int(1)
+    }
+
+    pub fn from_int(n: i64) -> Self {
+        Self { coeff: BigInt::from(n), exp: 0 }.normalized()
+    }
+
+    /// The shortest decimal that reads back as `v`.
+    pub fn from_f64(v: f64) -> Option<Self> {
+        if !v.is_finite() {
+            return None;
+        }
+        let text = format!("{:e}", v.abs());
+        let d = Context::new(20, Rounding::HalfEven).parse(&text)?;
+        Some(if v < 0. { -d } else { d })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.coeff.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.coeff.is_negative()
+    }
+
+    /// The exponent of the leading digit, as in scientific notation.
+    pub fn adjusted(&self) -> i64 {
+        self.exp + digit_count(&self.coeff) - 1
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        match self.exp {
+            0 => self.coeff.to_i64(),
+            e if e > 0 && e < 19 => (&self.coeff * &pow10(e)).to_i64(),
+            _ => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        format!("{}e{}", self.coeff, self.exp).parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn abs(&self) -> Self {
+        Self { coeff: self.coeff.abs(), exp: self.exp }
+    }
+
+    pub fn floor(&self) -> Self {
+        if self.exp >= 0 {
+            return self.clone();
+        }
+        // below 1 in size, without scaling by a power of ten as small
+        if self.adjusted() < 0 {
+            return if self.is_negative() { Self::from_int(-1) } else { Self::zero() };
+        }
+        Self { coeff: self.coeff.div_floor(&pow10(-self.exp)), exp: 0 }.normalized()
+    }
+
+    pub fn ceil(&self) -> Self {
+        -(-self.clone()).floor()
+    }
+
+    /// Rounds half away from zero, like `f64::round`.
+    pub fn round(&self) -> Self {
+        if self.exp >= 0 {
+            return self.clone();
+        }
+        // below 0.1 in size, which rounds to 0 however small it is
+        if self.adjusted() < -1 {
+            return Self::zero();
+        }
+        let half = Decimal { coeff: BigInt::from(5u64), exp: -1 };
+        let rounded = add_exact(&self.abs(), &half).floor();
+        if self.is_negative() { -rounded } else { rounded }
+    }
+
+    fn normalized(mut self) -> Self {
+        if self.coeff.is_zero() {
+            return Self::zero();
+        }
+        let ten = BigInt::from(10u64);
+        loop {
+            let (q, r) = self.coeff.div_rem(&ten);
+            if !r.is_zero() {
+                return self;
+            }
+            self.coeff = q;
+            self.exp += 1;
+        }
+    }
+}
+
+fn pow10(n: i64) -> BigInt {
+    BigInt::from(10u64).pow(n as u32)
+}
+
+fn digit_count(n: &BigInt) -> i64 {
+    match n.is_zero() {
+        true => 1,
+        false => n.abs().to_string().len() as i64,
+    }
+}
+
+fn add_exact(a: &Decimal, b: &Decimal) -> Decimal {
+    let exp = a.exp.min(b.exp);
+    let coeff = &(&a.coeff * &pow10(a.exp - exp)) + &(&b.coeff * &pow10(b.exp - exp));
+    Decimal { coeff, exp }
+}
+
+/// `coeff * 10^exp`, plus a nonzero digit past the end when digits were
+/// lost, so that rounding still sees them.
+fn with_sticky(coeff: BigInt, exp: i64, inexact: bool) -> Decimal {
+    if !inexact {
+        return Decimal { coeff, exp };
+    }
+    let sticky = BigInt::from(if coeff.is_negative() { -1i64 } else { 1 });
+    Decimal {
+        coeff: &(&coeff * &BigInt::from(10u64)) + &sticky,
+        exp: exp - 1,
+    }
+}
+
+impl Default for Decimal {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+impl std::ops::Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        Decimal { coeff: -self.coeff, exp: self.exp }
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let sign = |d: &Decimal| if d.is_zero() { 0 } else if d.is_negative() { -1 } else { 1 };
+        let by_sign = sign(self).cmp(&sign(other));
+        if by_sign != Ordering::Equal || self.is_zero() {
+            return by_sign;
+        }
+        let by_magnitude = match self.adjusted().cmp(&other.adjusted()) {
+            Ordering::Equal => {
+                let exp = self.exp.min(other.exp);
+                let a = &self.coeff.abs() * &pow10(self.exp - exp);
+                let b = &other.coeff.abs() * &pow10(other.exp - exp);
+                a.cmp(&b)
+            }
+            o => o,
+        };
+        if self.is_negative() { by_magnitude.reverse() } else { by_magnitude }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Plain notation for everyday magnitudes, `1.2345e-12` style otherwise.
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_negative() {
+            write!(f, "-")?;
+        }
+        let digits = self.coeff.abs().to_string();
+        let len = digits.len() as i64;
+        let adjusted = self.adjusted();
+
+        if self.exp >= 0 && adjusted < 21 {
+            write!(f, "{digits}{}", "0".repeat(self.exp as usize))
+        } else if self.exp < 0 && adjusted >= -7 {
+            match len + self.exp {
+                int if int > 0 => write!(f, "{}.{}", &digits[..int as usize], &digits[int as usize..]),
+                int => write!(f, "0.{}{digits}", "0".repeat(-int as usize)),
+            }
+        } else if len == 1 {
+            write!(f, "{digits}e{adjusted}")
+        } else {
+            write!(f, "{}.{}e{adjusted}", &digits[..1], &digits[1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digits(precision: u32) -> Context {
+        Context::new(precision, Rounding::HalfEven)
+    }
+
+    fn dec(text: &str) -> Decimal {
+        match text.strip_prefix('-') {
+            Some(text) => -digits(100).parse(text).unwrap(),
+            None => digits(100).parse(text).unwrap(),
+        }
+    }
+
+    fn rounded(text: &str, precision: u32, rounding: Rounding) -> String {
+        Context::new(precision, rounding).round(dec(text)).to_string()
+    }
+
+    #[test]
+    fn half_even_breaks_ties_to_an_even_digit() {
+        assert_eq!(rounded("2.345", 3, Rounding::HalfEven), "2.34");
+        assert_eq!(rounded("2.355", 3, Rounding::HalfEven), "2.36");
+        assert_eq!(rounded("-2.345", 3, Rounding::HalfEven), "-2.34");
+        assert_eq!(rounded("2.3451", 3, Rounding::HalfEven), "2.35");
+    }
+
+    #[test]
+    fn half_up_breaks_ties_away_from_zero() {
+        assert_eq!(rounded("2.345", 3, Rounding::HalfUp), "2.35");
+        assert_eq!(rounded("-2.345", 3, Rounding::HalfUp), "-2.35");
+        assert_eq!(rounded("2.3449", 3, Rounding::HalfUp), "2.34");
+    }
+
+    #[test]
+    fn down_truncates_towards_zero() {
+        assert_eq!(rounded("2.349", 3, Rounding::Down), "2.34");
+        assert_eq!(rounded("-2.349", 3, Rounding::Down), "-2.34");
+        assert_eq!(rounded("999.9", 3, Rounding::Down), "999");
+    }
+
+    #[test]
+    fn results_keep_the_precision() {
+        let (two, three) = (Decimal::from_int(2), Decimal::from_int(3));
+        assert_eq!(digits(5).div(&two, &three).unwrap().to_string(), "0.66667");
+        assert_eq!(Context::new(5, Rounding::Down).div(&two, &three).unwrap().to_string(), "0.66666");
+        assert_eq!(digits(3).parse("123456").unwrap().to_string(), "123000");
+        assert_eq!(digits(4).add(&dec("1e20"), &dec("1")).to_string(), "100000000000000000000");
+        // digits far below the precision still decide a tie
+        assert_eq!(digits(2).add(&dec("2.5"), &dec("1e-50")).to_string(), "2.5");
+        assert_eq!(digits(1).add(&dec("2.5"), &dec("1e-50")).to_string(), "3");
+    }
+
+    #[test]
+    fn sqrt_exp_and_ln_at_high_precision() {
+        let ctx = digits(50);
+        assert_eq!(
+            ctx.sqrt(&Decimal::from_int(2)).unwrap().to_string(),
+            "1.4142135623730950488016887242096980785696718753769"
+        );
+        assert_eq!(
+            ctx.exp(&Decimal::one()).unwrap().to_string(),
+            "2.7182818284590452353602874713526624977572470937"
+        );
+        assert_eq!(
+            digits(40).ln(&Decimal::from_int(2)).unwrap().to_string(),
+            "0.6931471805599453094172321214581765680755"
+        );
+        assert_eq!(digits(30).pi().to_string(), "3.14159265358979323846264338328");
+        assert_eq!(ctx.sqrt(&Decimal::from_int(-1)), None);
+        assert_eq!(ctx.ln(&Decimal::zero()), None);
+    }
+
+    #[test]
+    fn floor_ceil_and_round_of_tiny_values_are_immediate() {
+        // these used to build 10^99999999 before giving up
+        let tiny = Decimal { coeff: BigInt::one(), exp: -99_999_999 };
+        assert_eq!(tiny.floor(), Decimal::zero());
+        assert_eq!(tiny.ceil(), Decimal::one());
+        assert_eq!(tiny.round(), Decimal::zero());
+        assert_eq!((-tiny.clone()).floor(), Decimal::from_int(-1));
+        assert_eq!((-tiny).ceil(), Decimal::zero());
+    }
+
+    #[test]
+    fn floor_ceil_and_round() {
+        assert_eq!(dec("-2.5").floor(), Decimal::from_int(-3));
+        assert_eq!(dec("-2.5").ceil(), Decimal::from_int(-2));
+        assert_eq!(dec("-2.5").round(), Decimal::from_int(-3));
+        assert_eq!(dec("0.5").round(), Decimal::one());
+        assert_eq!(dec("0.05").round(), Decimal::zero());
+    }
+
+    #[test]
+    fn parse_refuses_huge_exponents() {
+        assert!(digits(10).parse("1e-100000").is_some());
+        assert_eq!(digits(10).parse("1e-100001"), None);
+        assert_eq!(digits(10).parse("1e99999999"), None);
+    }
+}
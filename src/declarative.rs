@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Assign, Program},
+    error::EvalError,
+    eval::EvalResult,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    Started,
+    Done,
+}
+
+/// The assignments of `program` reordered so that each comes after the
+/// ones it uses, whatever order they were written in. Otherwise they keep
+/// their order. Every name may be defined only once, and no variable may
+/// use its own value, though functions may still call themselves.
+pub fn order(program: Program) -> EvalResult<Program> {
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (i, a) in program.body.iter().enumerate() {
+        if let Some(&first) = index.get(a.name.as_str()) {
+            let line = program.body[first].span.line;
+            return Err(EvalError::Redefinition(a.name.clone(), line).at(a.span));
+        }
+        index.insert(&a.name, i);
+    }
+
+    let uses = program
+        .body
+        .iter()
+        .map(|a| a.dependencies().iter().filter_map(|n| index.get(n.as_str()).copied()).collect())
+        .collect::<Vec<Vec<usize>>>();
+    let mut sorter = Sorter {
+        body: &program.body,
+        uses: &uses,
+        visits: vec![None; uses.len()],
+        stack: Vec::new(),
+        order: Vec::new(),
+    };
+    for i in 0..program.body.len() {
+        sorter.visit(i)?;
+    }
+
+    let order = sorter.order;
+    let mut body = program.body.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(Program { body: order.into_iter().map(|i| body[i].take().expect("each assignment is ordered once")).collect() })
+}
+
+/// A depth-first walk of the uses, which lists each assignment after
+/// those it uses.
+struct Sorter<'a> {
+    body: &'a [Assign],
+    uses: &'a [Vec<usize>],
+    visits: Vec<Option<Visit>>,
+    /// The assignments being visited, each used by the one before.
+    stack: Vec<usize>,
+    order: Vec<usize>,
+}
+
+impl Sorter<'_> {
+    fn visit(&mut self, i: usize) -> EvalResult<()> {
+        match self.visits[i] {
+            Some(Visit::Done) => return Ok(()),
+            Some(Visit::Started) => {
+                let cycle = &self.stack[self.stack.iter().position(|&s| s == i).expect("started ones are stacked")..];
+                // functions are only run when called, so they may call each other
+                if cycle.iter().all(|&c| !self.body[c].params.is_empty()) {
+                    return Ok(());
+                }
+                let path = cycle.iter().chain([&i]).map(|&c| (self.body[c].name.clone(), Some(self.body[c].span.line)));
+                return Err(EvalError::Cycle(path.collect()).at(self.body[i].span));
+            }
+            None => {}
+        }
+        self.visits[i] = Some(Visit::Started);
+        self.stack.push(i);
+        for &used in &self.uses[i] {
+            self.visit(used)?;
+        }
+        self.stack.pop();
+        self.visits[i] = Some(Visit::Done);
+        self.order.push(i);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Parser, lexer::tokenize};
+
+    fn ordered(source: &str) -> EvalResult<Vec<String>> {
+        let program = Parser::new(tokenize(source).unwrap()).parse_program().unwrap();
+        order(program).map(|p| p.body.into_iter().map(|a| a.name).collect())
+    }
+
+    fn cycle(path: &[(&str, usize)]) -> EvalError {
+        EvalError::Cycle(path.iter().map(|&(n, l)| (n.to_string(), Some(l))).collect())
+    }
+
+    #[test]
+    fn each_comes_after_the_ones_it_uses() {
+        assert_eq!(ordered("area = pi * r^2\nr = d / 2\nd = 4").unwrap(), ["d", "r", "area"]);
+        assert_eq!(ordered("b = 2\na = 1\nc = a + b").unwrap(), ["b", "a", "c"]);
+        assert_eq!(ordered("y = sq(3)\nsq(x) = x^2").unwrap(), ["sq", "y"]);
+    }
+
+    #[test]
+    fn a_name_may_be_defined_once() {
+        let e = ordered("a = 1\nb = 2\na = 3").unwrap_err();
+        assert_eq!(e.kind(), &EvalError::Redefinition("a".to_string(), 1));
+        assert_eq!(e.span().unwrap().line, 3);
+    }
+
+    #[test]
+    fn a_variable_cannot_use_itself() {
+        assert_eq!(ordered("a = a + 1").unwrap_err().kind(), &cycle(&[("a", 1), ("a", 1)]));
+        let e = ordered("a = b + 1\nb = c * 2\nc = a").unwrap_err();
+        assert_eq!(e.kind(), &cycle(&[("a", 1), ("b", 2), ("c", 3), ("a", 1)]));
+    }
+
+    #[test]
+    fn a_cycle_through_a_function_still_needs_a_variable_first() {
+        let e = ordered("f(x) = x + k\nk = f(1)").unwrap_err();
+        assert_eq!(e.kind(), &cycle(&[("f", 1), ("k", 2), ("f", 1)]));
+    }
+
+    #[test]
+    fn functions_may_call_each_other() {
+        let source = "even(n) = if n == 0 then 1 else odd(n - 1)\nodd(n) = if n == 0 then 0 else even(n - 1)\nx = even(4)";
+        assert_eq!(ordered(source).unwrap(), ["odd", "even", "x"]);
+        assert_eq!(ordered("fact(n) = if n < 2 then 1 else n * fact(n - 1)").unwrap(), ["fact"]);
+    }
+}
@@ -0,0 +1,273 @@
+use std::{env, fmt::Write, io::IsTerminal};
+
+use crate::{
+    error::{BuildError, CompileError, EngineError, EvalError, LexerError, ParsingError},
+    eval::Warning,
+    lexer::Span,
+};
+
+/// An error ready to be shown to the user, rustc style:
+///
+/// ```text
+/// error: Dividing by zero is not allowed
+///  --> area.math:3:9
+///   |
+/// 3 | a = 1 / (b - b)
+///   |         ^^^^^^^
+///   = help: ...
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub span: Option<Span>,
+    pub help: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Option<Span>) -> Self {
+        Self {
+            level: Level::Error,
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Option<Span>) -> Self {
+        Self {
+            level: Level::Warning,
+            ..Self::new(message, span)
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Renders the report. `file` is the name shown after `-->`, and
+    /// `source` the whole text the span points into.
+    pub fn render(&self, file: &str, source: &str, color: bool) -> String {
+        let paint = |code: &str, text: &str| match color {
+            true => format!("\x1b[{code}m{text}\x1b[0m"),
+            false => text.to_string(),
+        };
+        let blue = |text: &str| paint("1;34", text);
+        let (label, label_color) = match self.level {
+            Level::Error => ("error", "1;31"),
+            Level::Warning => ("warning", "1;33"),
+        };
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{}: {}", paint(label_color, label), paint("1", &self.message));
+
+        let Some((span, (line, start, end))) = self.span.and_then(|span| Some((span, excerpt(source, span)?))) else {
+            match self.span {
+                Some(span) => {
+                    let _ = writeln!(out, " {} {file}:{}:{}", blue("-->"), span.line, span.col);
+                }
+                None if !file.is_empty() => {
+                    let _ = writeln!(out, " {} {file}", blue("-->"));
+                }
+                None => {}
+            }
+            self.render_help(&mut out, "", &blue);
+            return out;
+        };
+
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // keep tabs in the padding so the carets line up with the source
+        let pad: String = line[..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(line[start..end].chars().count().max(1));
+
+        let _ = writeln!(out, "{gutter}{} {file}:{}:{}", blue("-->"), span.line, span.col);
+        let _ = writeln!(out, "{gutter} {}", blue("|"));
+        let _ = writeln!(out, "{} {} {line}", blue(&number), blue("|"));
+        let _ = writeln!(out, "{gutter} {} {pad}{}", blue("|"), paint(label_color, &carets));
+        self.render_help(&mut out, &gutter, &blue);
+        out
+    }
+
+    fn render_help(&self, out: &mut String, gutter: &str, blue: &dyn Fn(&str) -> String) {
+        if let Some(help) = &self.help {
+            let _ = writeln!(out, "{gutter} {} help: {help}", blue("="));
+        }
+    }
+}
+
+/// The line of `source` that `span` starts on, with the byte range of the
+/// span within it, or `None` when the span doesn't point into `source`,
+/// as when an error comes from text other than the one given.
+fn excerpt(source: &str, span: Span) -> Option<(&str, usize, usize)> {
+    let before = source.get(..span.start)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    if before.matches('\n').count() + 1 != span.line {
+        return None;
+    }
+    let line = source[line_start..].lines().next().unwrap_or("");
+    let start = (span.start - line_start).min(line.len());
+    let end = (span.end.max(span.start) - line_start).clamp(start, line.len());
+    // a span ending inside a character is cut back to the one before
+    let end = (start..=end).rev().find(|&i| line.is_char_boundary(i)).unwrap_or(start);
+    Some((line, start, end))
+}
+
+/// Whether diagnostics should be colored, from a `--color=` value:
+/// `always`, `never`, or `auto` (the default), which colors only when
+/// stderr is a terminal and `NO_COLOR` isn't set.
+pub fn use_color(choice: Option<&str>) -> bool {
+    match choice {
+        Some("always") => true,
+        Some("never") => false,
+        _ => std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+pub trait Diagnose {
+    fn diagnostic(&self) -> Diagnostic;
+}
+
+impl Diagnose for LexerError {
+    fn diagnostic(&self) -> Diagnostic {
+        let d = Diagnostic::new(self.kind().to_string(), self.span());
+        match self.kind() {
+            LexerError::UnrecognizedChar(_) => {
+                d.with_help("expressions may only use numbers, names, operators, parentheses and commas")
+            }
+            LexerError::UnterminatedComment => {
+                d.with_help("close it with `*/`; block comments nest, so each `/*` needs its own")
+            }
+            _ => d,
+        }
+    }
+}
+
+impl Diagnose for ParsingError {
+    fn diagnostic(&self) -> Diagnostic {
+        let d = Diagnostic::new(self.kind().to_string(), self.span());
+        match self.kind() {
+            ParsingError::InvalidAssignment => {
+                d.with_help("each line of a file is an assignment like `name = expression`")
+            }
+            ParsingError::UnexpectedEof => d.with_help("the expression ends too early"),
+            ParsingError::MissingOperator(..) => d.with_help(
+                "whitespace separates values; add an operator such as `*`, or write `1_000` to group digits",
+            ),
+            _ => d,
+        }
+    }
+}
+
+impl Diagnose for EvalError {
+    fn diagnostic(&self) -> Diagnostic {
+        let d = Diagnostic::new(self.kind().to_string(), self.span());
+        match self.kind() {
+            EvalError::VariableDoesNotExists(var) => {
+                d.with_help(format!("assign `{var}` before the line that uses it"))
+            }
+            EvalError::RecursionLimit(_) => {
+                d.with_help("make sure the function has an `if` that stops calling itself")
+            }
+            EvalError::Cycle(_) => {
+                d.with_help("a variable can't be computed from its own value; give one of them a plain value")
+            }
+            EvalError::Redefinition(..) => {
+                d.with_help("in declarative mode each name is defined once; rename one of them")
+            }
+            EvalError::NotConverged(..) => d.with_help(
+                "give `solve` a starting guess, `solve(eq, x, guess)`, or a range the answer is in, \
+                 `solve(eq, x, low, high)`; the equation may also have no real solution",
+            ),
+            EvalError::NoSignChange(..) => {
+                d.with_help("one side must be larger at one end of the range and smaller at the other")
+            }
+            _ => d,
+        }
+    }
+}
+
+impl Diagnose for EngineError {
+    fn diagnostic(&self) -> Diagnostic {
+        match self {
+            EngineError::Lexer(e) => e.diagnostic(),
+            EngineError::Parsing(e) => e.diagnostic(),
+            EngineError::Eval(e) => e.diagnostic(),
+        }
+    }
+}
+
+impl Diagnose for Warning {
+    fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::warning(self.message.clone(), Some(self.span))
+    }
+}
+
+impl Diagnose for CompileError {
+    fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.to_string(), None)
+    }
+}
+
+impl Diagnose for BuildError {
+    fn diagnostic(&self) -> Diagnostic {
+        let d = Diagnostic::new(self.to_string(), None);
+        match self {
+            BuildError::ToolNotFound(..) => {
+                d.with_help("install qbe (https://c9x.me/compile/) and a C compiler, or set QBE and CC")
+            }
+            _ => d,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize, line: usize, col: usize) -> Span {
+        Span { start, end, line, col }
+    }
+
+    fn render(span: Span, source: &str) -> String {
+        Diagnostic::new("Dividing by zero is not allowed", Some(span)).render("<repl>", source, false)
+    }
+
+    #[test]
+    fn points_at_the_span_on_its_line() {
+        let source = "a = 1\nb = a / (a - a)\n";
+        let expected = "error: Dividing by zero is not allowed\n --> <repl>:2:9\n  |\n2 | b = a / (a - a)\n  |         ^^^^^^^\n";
+        assert_eq!(render(span(14, 21, 2, 9), source), expected);
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        let rendered = render(span(13, 14, 1, 8), "éééééé = 1/0");
+        assert!(rendered.ends_with("1 | éééééé = 1/0\n  |        ^\n"), "{rendered}");
+    }
+
+    #[test]
+    fn a_span_from_other_text_has_no_excerpt() {
+        let located = "error: Dividing by zero is not allowed\n --> <repl>:1:12\n";
+        // inside a character, past the end, and on another line
+        assert_eq!(render(span(11, 12, 1, 12), "r = éééééé + f(0)"), located);
+        assert_eq!(render(span(11, 12, 1, 12), "f(0)"), located);
+        assert_eq!(render(span(11, 12, 1, 12), "a = 1\nb = 2 + 3 + 4"), located);
+    }
+
+    #[test]
+    fn a_span_past_its_line_is_cut_to_it() {
+        let rendered = render(span(2, 40, 1, 3), "1 +\n2");
+        assert!(rendered.ends_with("1 | 1 +\n  |   ^\n"), "{rendered}");
+    }
+}
@@ -0,0 +1,451 @@
+use crate::{
+    ast::{ExprKind, Expression},
+    error::EvalError,
+    eval::{self, EvalResult},
+    lexer::{Number, Span},
+    optimize::{self, Options},
+    bigint::BigInt,
+    rational::Rational,
+    value::Mode,
+};
+
+/// The derivative of `e` with respect to the variable `var`, simplified:
+/// `diff(pi*r^2, r)` is `2*pi*r`. Other names count as constants, and
+/// calls to user-defined functions can't be differentiated. `e` is first
+/// simplified for `mode`, as `--simplify` would.
+pub fn derivative(e: &Expression, var: &str, mode: Mode) -> EvalResult<Expression> {
+    let e = optimize::simplify(e.clone(), Options { mode, assume_finite: false });
+    Differentiator { var, span: e.span }.diff(&e)
+}
+
+/// A term of a sum: its constant factor, the rest, if it isn't only a
+/// constant, and the rest's source, to find the terms that only differ by
+/// their constant.
+type Term = (Rational, Option<Expression>, String);
+
+/// A factor of a product, its source, to find equal ones, and its power.
+type Power = (Expression, String, i64);
+
+struct Differentiator<'a> {
+    var: &'a str,
+    /// Where the expression being differentiated is, for the new nodes.
+    span: Span,
+}
+
+impl Differentiator<'_> {
+    fn diff(&self, e: &Expression) -> EvalResult<Expression> {
+        match &e.kind {
+            ExprKind::Var(name) if name == self.var => Ok(self.int(1)),
+            ExprKind::Var(_) | ExprKind::Num(_) | ExprKind::Quantity(..) => Ok(self.int(0)),
+            ExprKind::Parenthed(inner) => self.diff(inner),
+            ExprKind::Array(elements) => {
+                let elements = elements.iter().map(|e| self.diff(e)).collect::<EvalResult<_>>()?;
+                Ok(self.node(ExprKind::Array(elements)))
+            }
+            ExprKind::If(cond, then, otherwise) => {
+                let (then, otherwise) = (self.diff(then)?, self.diff(otherwise)?);
+                Ok(self.node(ExprKind::If(cond.clone(), Box::new(then), Box::new(otherwise))))
+            }
+            ExprKind::Operation(op, operands) => self.operation(op, operands),
+            ExprKind::Call(name, args) => self.call(name, args),
+            ExprKind::Convert(..) => Err(EvalError::NotDifferentiable("a unit conversion".to_string())),
+        }
+    }
+
+    fn operation(&self, op: &str, operands: &[Expression]) -> EvalResult<Expression> {
+        if let [u] = operands {
+            return match op {
+                "+" => self.diff(u),
+                "-" => Ok(self.neg(self.diff(u)?)),
+                _ => Err(EvalError::NotDifferentiable(format!("the operator `{op}`"))),
+            };
+        }
+        let (u, v) = (&operands[0], &operands[1]);
+        let (du, dv) = (self.diff(u)?, self.diff(v)?);
+        match op {
+            "+" => Ok(self.add(du, dv)),
+            "-" => Ok(self.sub(du, dv)),
+            "*" => Ok(self.add(self.mul(du, v.clone()), self.mul(u.clone(), dv))),
+            "/" if is_zero(&dv) => Ok(self.div(du, v.clone())),
+            "/" => {
+                let numerator = self.sub(self.mul(du, v.clone()), self.mul(u.clone(), dv));
+                Ok(self.div(numerator, self.pow(v.clone(), self.int(2))))
+            }
+            // n*u^(n-1)*u' when the exponent is constant
+            "^" if is_zero(&dv) => {
+                let exponent = self.sub(v.clone(), self.int(1));
+                Ok(self.mul(self.mul(v.clone(), self.pow(u.clone(), exponent)), du))
+            }
+            // a^v*ln(a)*v' when the base is
+            "^" if is_zero(&du) => Ok(self.mul(self.mul(self.pow(u.clone(), v.clone()), self.ln(u)), dv)),
+            "^" => {
+                let inner = self.add(self.mul(dv, self.ln(u)), self.div(self.mul(v.clone(), du), u.clone()));
+                Ok(self.mul(self.pow(u.clone(), v.clone()), inner))
+            }
+            _ => Err(EvalError::NotDifferentiable(format!("the operator `{op}`"))),
+        }
+    }
+
+    /// The chain rule: `f'(u) * u'` for the builtins of one argument.
+    fn call(&self, name: &str, args: &[Expression]) -> EvalResult<Expression> {
+        let call = |name: &str, args: Vec<Expression>| self.node(ExprKind::Call(name.to_string(), args));
+        if let [y, x] = args {
+            let (dy, dx) = (self.diff(y)?, self.diff(x)?);
+            return match name {
+                // log(b, x) = ln(x) / ln(b)
+                "log" => self.diff(&self.div(self.ln(x), self.ln(y))),
+                "atan2" => {
+                    let numerator = self.sub(self.mul(x.clone(), dy), self.mul(y.clone(), dx));
+                    let denominator = self.add(self.pow(x.clone(), self.int(2)), self.pow(y.clone(), self.int(2)));
+                    Ok(self.div(numerator, denominator))
+                }
+                "hypot" => {
+                    let numerator = self.add(self.mul(y.clone(), dy), self.mul(x.clone(), dx));
+                    Ok(self.div(numerator, call("hypot", args.to_vec())))
+                }
+                _ => Err(self.not_differentiable(name)),
+            };
+        }
+        let [u] = args else {
+            return Err(self.not_differentiable(name));
+        };
+        let du = self.diff(u)?;
+        let one_minus_square = || self.sub(self.int(1), self.pow(u.clone(), self.int(2)));
+        let derivative = match name {
+            "sqrt" => self.div(self.int(1), self.mul(self.int(2), call("sqrt", vec![u.clone()]))),
+            "exp" => call("exp", vec![u.clone()]),
+            "ln" => self.div(self.int(1), u.clone()),
+            "log10" => self.div(self.int(1), self.mul(u.clone(), self.ln(&self.int(10)))),
+            "sin" => call("cos", vec![u.clone()]),
+            "cos" => self.neg(call("sin", vec![u.clone()])),
+            "tan" => self.div(self.int(1), self.pow(call("cos", vec![u.clone()]), self.int(2))),
+            "asin" => self.div(self.int(1), call("sqrt", vec![one_minus_square()])),
+            "acos" => self.neg(self.div(self.int(1), call("sqrt", vec![one_minus_square()]))),
+            "atan" => self.div(self.int(1), self.add(self.int(1), self.pow(u.clone(), self.int(2)))),
+            "sinh" => call("cosh", vec![u.clone()]),
+            "cosh" => call("sinh", vec![u.clone()]),
+            "tanh" => self.div(self.int(1), self.pow(call("cosh", vec![u.clone()]), self.int(2))),
+            "asinh" => {
+                let square_plus_one = self.add(self.pow(u.clone(), self.int(2)), self.int(1));
+                self.div(self.int(1), call("sqrt", vec![square_plus_one]))
+            }
+            "acosh" => {
+                let square_minus_one = self.sub(self.pow(u.clone(), self.int(2)), self.int(1));
+                self.div(self.int(1), call("sqrt", vec![square_minus_one]))
+            }
+            "atanh" => self.div(self.int(1), one_minus_square()),
+            "abs" => self.div(u.clone(), call("abs", vec![u.clone()])),
+            // flat between the steps
+            "floor" | "ceil" | "round" => self.int(0),
+            _ => return Err(self.not_differentiable(name)),
+        };
+        Ok(self.mul(derivative, du))
+    }
+
+    fn not_differentiable(&self, name: &str) -> EvalError {
+        match eval::builtin(name) {
+            Some(_) => EvalError::NotDifferentiable(format!("`{name}`")),
+            None => EvalError::NotDifferentiable(format!("`{name}`, which isn't a built-in function")),
+        }
+    }
+
+    fn node(&self, kind: ExprKind) -> Expression {
+        Expression::new(kind, self.span)
+    }
+
+    fn operation_of(&self, op: &str, operands: Vec<Expression>) -> Expression {
+        self.node(ExprKind::Operation(op.to_string(), operands))
+    }
+
+    fn int(&self, n: i64) -> Expression {
+        self.number(Rational::from_int(n))
+    }
+
+    /// `r` as a literal, or as a fraction of two when it has no exact
+    /// decimal expansion.
+    fn number(&self, r: Rational) -> Expression {
+        if r.is_negative() {
+            return self.neg(self.number(-r));
+        }
+        let literal = |text: String| {
+            let value = text.parse().expect("decimal expansions are valid floats");
+            self.node(ExprKind::Num(Number { value, text, imaginary: false }))
+        };
+        match r.to_decimal(40) {
+            (text, true) => literal(text),
+            _ => {
+                let (numer, denom) = (r.numer().to_string(), r.denom().to_string());
+                self.operation_of("/", vec![literal(numer), literal(denom)])
+            }
+        }
+    }
+
+    fn ln(&self, u: &Expression) -> Expression {
+        match &u.kind {
+            ExprKind::Var(e) if e == "e" => self.int(1),
+            _ => self.node(ExprKind::Call("ln".to_string(), vec![u.clone()])),
+        }
+    }
+
+    fn neg(&self, u: Expression) -> Expression {
+        match u.kind {
+            _ if is_zero(&u) => u,
+            ExprKind::Operation(op, mut operands) if op == "-" && operands.len() == 1 => operands.pop().unwrap(),
+            kind => self.operation_of("-", vec![Expression::new(kind, u.span)]),
+        }
+    }
+
+    fn add(&self, a: Expression, b: Expression) -> Expression {
+        self.sum([(a, false), (b, false)])
+    }
+
+    fn sub(&self, a: Expression, b: Expression) -> Expression {
+        self.sum([(a, false), (b, true)])
+    }
+
+    /// The sum of `terms`, those marked subtracted, with the terms that
+    /// only differ by a constant factor merged, so `x + 2*x` becomes `3*x`.
+    fn sum<const N: usize>(&self, terms: [(Expression, bool); N]) -> Expression {
+        let mut merged = Vec::new();
+        for (t, subtracted) in terms {
+            self.terms(t, subtracted, &mut merged);
+        }
+        let mut terms = merged.into_iter().filter(|(c, ..)| !c.is_zero()).map(|(c, rest, _)| {
+            let magnitude = self.number(c.abs());
+            (c.is_negative(), rest.map_or(magnitude.clone(), |rest| self.mul(magnitude, rest)))
+        });
+        let Some((negative, first)) = terms.next() else {
+            return self.int(0);
+        };
+        let first = if negative { self.neg(first) } else { first };
+        terms.fold(first, |acc, (negative, t)| self.operation_of(if negative { "-" } else { "+" }, vec![acc, t]))
+    }
+
+    fn terms(&self, e: Expression, subtracted: bool, merged: &mut Vec<Term>) {
+        match e.kind {
+            ExprKind::Operation(op, mut operands) if operands.len() == 2 && (op == "+" || op == "-") => {
+                let b = operands.pop().unwrap();
+                self.terms(operands.pop().unwrap(), subtracted, merged);
+                self.terms(b, subtracted != (op == "-"), merged);
+            }
+            ExprKind::Operation(op, mut operands) if operands.len() == 1 && op == "-" => {
+                self.terms(operands.pop().unwrap(), !subtracted, merged);
+            }
+            kind => {
+                let (c, rest) = split_coefficient(Expression::new(kind, e.span));
+                let c = if subtracted { -c } else { c };
+                // constants all go together, where the first one was
+                let source = rest.as_ref().map_or(String::new(), Expression::to_source);
+                match merged.iter_mut().find(|(_, _, s)| *s == source) {
+                    Some((total, ..)) => *total = total.clone() + c,
+                    None => merged.push((c, rest, source)),
+                }
+            }
+        }
+    }
+
+    fn mul(&self, a: Expression, b: Expression) -> Expression {
+        self.product([(a, 1), (b, 1)])
+    }
+
+    fn div(&self, a: Expression, b: Expression) -> Expression {
+        self.product([(a, 1), (b, -1)])
+    }
+
+    /// The product of `factors`, each to its power, written with the
+    /// constants multiplied together in front and equal factors merged,
+    /// so `pi * (2 * r)` becomes `2*pi*r` and `x * x / x` becomes `x`.
+    fn product<const N: usize>(&self, factors: [(Expression, i64); N]) -> Expression {
+        let mut constant = Rational::from_int(1i64);
+        let mut powers = Vec::new();
+        for (f, exp) in factors {
+            self.factors(f, exp, &mut constant, &mut powers);
+        }
+        if constant.is_zero() {
+            return self.int(0);
+        }
+        powers.retain(|(_, _, exp)| *exp != 0);
+
+        let negative = constant.is_negative();
+        let constant = if negative { -constant } else { constant };
+        // the sign goes on the first factor, for `-2*x` rather than `-(2*x)`
+        let side = |constant: &BigInt, above: bool, negative: bool| {
+            let mut side = powers
+                .iter()
+                .filter(|(_, _, exp)| (*exp > 0) == above)
+                .map(|(f, _, exp)| self.pow(f.clone(), self.int(exp.abs())))
+                .collect::<Vec<_>>();
+            if *constant != BigInt::one() || (above && side.is_empty()) {
+                side.insert(0, self.number(Rational::from_int(constant.clone())));
+            }
+            if negative {
+                side[0] = self.neg(side[0].clone());
+            }
+            side.into_iter().reduce(|acc, f| self.operation_of("*", vec![acc, f]))
+        };
+        let numerator = side(constant.numer(), true, negative).expect("the numerator has a constant when empty");
+        match side(constant.denom(), false, false) {
+            Some(denominator) => self.operation_of("/", vec![numerator, denominator]),
+            None => numerator,
+        }
+    }
+
+    /// Splits `e`, to the power `exp`, into the constant and the other
+    /// factors, which are kept with their source to find equal ones.
+    fn factors(&self, e: Expression, exp: i64, constant: &mut Rational, powers: &mut Vec<Power>) {
+        if let Some(c) = coefficient(&e).filter(|c| exp > 0 || !c.is_zero()) {
+            *constant = match c.pow(exp) {
+                Some(power) => constant.clone() * power,
+                None => unreachable!("only zero has no negative powers"),
+            };
+            return;
+        }
+        match e.kind {
+            ExprKind::Operation(op, mut operands) if operands.len() == 2 && (op == "*" || op == "/") => {
+                let b = operands.pop().unwrap();
+                self.factors(operands.pop().unwrap(), exp, constant, powers);
+                self.factors(b, if op == "*" { exp } else { -exp }, constant, powers);
+            }
+            ExprKind::Operation(op, mut operands) if operands.len() == 1 && op == "-" => {
+                if exp % 2 != 0 {
+                    *constant = -constant.clone();
+                }
+                self.factors(operands.pop().unwrap(), exp, constant, powers);
+            }
+            ExprKind::Operation(op, mut operands) if op == "^" && whole(&operands[1]).is_some() => {
+                let k = whole(&operands.pop().unwrap()).unwrap();
+                self.factors(operands.pop().unwrap(), exp * k, constant, powers);
+            }
+            kind => {
+                let f = Expression::new(kind, e.span);
+                let source = f.to_source();
+                match powers.iter_mut().find(|(_, s, _)| *s == source) {
+                    Some((_, _, e)) => *e += exp,
+                    None => powers.push((f, source, exp)),
+                }
+            }
+        }
+    }
+
+    fn pow(&self, a: Expression, b: Expression) -> Expression {
+        match coefficient(&b) {
+            Some(y) if y.is_zero() => self.int(1),
+            Some(y) if y == Rational::from_int(1i64) => a,
+            _ => self.operation_of("^", vec![a, b]),
+        }
+    }
+}
+
+/// A term split into its constant factor and the rest, which is `None`
+/// when the term is only a constant: `2*pi*r` is 2 and `pi*r`.
+fn split_coefficient(e: Expression) -> (Rational, Option<Expression>) {
+    if let Some(c) = coefficient(&e) {
+        return (c, None);
+    }
+    match e.kind {
+        ExprKind::Operation(op, mut operands) if op == "*" && operands.len() == 2 => {
+            let b = operands.pop().unwrap();
+            match split_coefficient(operands.pop().unwrap()) {
+                (c, Some(rest)) => (c, Some(Expression::new(ExprKind::Operation(op, vec![rest, b]), e.span))),
+                (c, None) => (c, Some(b)),
+            }
+        }
+        ExprKind::Operation(op, mut operands) if op == "-" && operands.len() == 1 => {
+            let (c, rest) = split_coefficient(operands.pop().unwrap());
+            (-c, rest)
+        }
+        kind => (Rational::from_int(1i64), Some(Expression::new(kind, e.span))),
+    }
+}
+
+/// The value of a whole constant exponent, as in `x^2`.
+fn whole(e: &Expression) -> Option<i64> {
+    coefficient(e).filter(Rational::is_integer).and_then(|c| c.numer().to_string().parse().ok())
+}
+
+/// The exact value of a literal, a negated one, or a fraction of two.
+fn coefficient(e: &Expression) -> Option<Rational> {
+    match &e.kind {
+        ExprKind::Num(n) if !n.imaginary => Rational::parse(&n.text),
+        ExprKind::Parenthed(inner) => coefficient(inner),
+        ExprKind::Operation(op, operands) => match (op.as_str(), operands.as_slice()) {
+            ("-", [u]) => coefficient(u).map(|c| -c),
+            ("/", [a, b]) => match (coefficient(a)?, coefficient(b)?) {
+                (_, b) if b.is_zero() => None,
+                (a, b) => Some(a / b),
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_zero(e: &Expression) -> bool {
+    coefficient(e).is_some_and(|c| c.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Parser, lexer::tokenize};
+
+    fn d(source: &str) -> EvalResult<String> {
+        let e = Parser::new(tokenize(source).unwrap()).parse_expression().unwrap();
+        derivative(&e, "x", Mode::Float).map(|d| d.to_source())
+    }
+
+    #[test]
+    fn sums_and_powers() {
+        assert_eq!(d("x^3 + 2*x^2 - x + 7").unwrap(), "3*x^2 + 4*x - 1");
+        assert_eq!(d("a*x + b").unwrap(), "a");
+        assert_eq!(d("5").unwrap(), "0");
+        assert_eq!(d("x*1 + 0").unwrap(), "1");
+    }
+
+    #[test]
+    fn product_rule() {
+        assert_eq!(d("x*sin(x)").unwrap(), "sin(x) + x*cos(x)");
+    }
+
+    #[test]
+    fn quotient_rule() {
+        assert_eq!(d("sin(x)/x").unwrap(), "(cos(x)*x - sin(x))/x^2");
+        assert_eq!(d("1/x").unwrap(), "-1/x^2");
+    }
+
+    #[test]
+    fn chain_rule() {
+        assert_eq!(d("sqrt(x^2 + 1)").unwrap(), "x/sqrt(x^2 + 1)");
+        assert_eq!(d("exp(3*x)").unwrap(), "3*exp(3*x)");
+        assert_eq!(d("ln(cos(x))").unwrap(), "-sin(x)/cos(x)");
+        assert_eq!(d("atan2(x, 1)").unwrap(), "1/(1 + x^2)");
+    }
+
+    #[test]
+    fn variable_exponents() {
+        assert_eq!(d("x^x").unwrap(), "x^x*(ln(x) + 1)");
+        assert_eq!(d("2^x").unwrap(), "2^x*ln(2)");
+        assert_eq!(d("e^x").unwrap(), "e^x");
+    }
+
+    #[test]
+    fn logarithms_in_any_base() {
+        assert_eq!(d("log(2, x)").unwrap(), "1/(x*ln(2))");
+        assert_eq!(d("log(x, 8)").unwrap(), "-ln(8)/(x*ln(x)^2)");
+    }
+
+    #[test]
+    fn conditionals_and_vectors_branch_by_branch() {
+        assert_eq!(d("if x > 0 then x^2 else -x").unwrap(), "if x > 0 then 2*x else -1");
+        assert_eq!(d("[x, x^2]").unwrap(), "[1, 2*x]");
+        assert_eq!(d("floor(x)").unwrap(), "0");
+    }
+
+    #[test]
+    fn calls_it_has_no_rule_for_are_errors() {
+        let error = |source| d(source).unwrap_err().kind().clone();
+        assert_eq!(error("f(x)"), EvalError::NotDifferentiable("`f`, which isn't a built-in function".to_string()));
+        assert_eq!(error("min(x, 1)"), EvalError::NotDifferentiable("`min`".to_string()));
+        assert_eq!(error("x > 1"), EvalError::NotDifferentiable("the operator `>`".to_string()));
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use crate::{
+    ast::{Assign, ExprKind, Expression, Parser, Program},
+    bindings::Definition,
+    diff,
+    error::EngineError,
+    eval::{self, Binding, EvalResult, Globals, HostFunction, Resolver, Warning},
+    lexer::{is_assignment, tokenize},
+    solve,
+    value::{Mode, Value},
+};
+
+pub type EngineResult<T> = Result<T, EngineError>;
+
+/// A session for evaluating programs and expressions from Rust. Variables
+/// and functions stay defined from one call to the next, as in the REPL.
+///
+/// ```
+/// use simple_math::Engine;
+///
+/// let mut engine = Engine::new();
+/// engine.eval_str("vat = 0.25").unwrap();
+/// engine.set_var("price", 80.);
+/// assert_eq!(engine.eval_str("price * (1 + vat)").unwrap().to_f64(), 100.);
+/// ```
+#[derive(Debug, Default)]
+pub struct Engine {
+    globals: Globals,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mode(mode: Mode) -> Self {
+        Self { globals: Globals::with_mode(mode) }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.globals.mode()
+    }
+
+    /// Changes how literals evaluated from now on are read.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.globals.set_mode(mode);
+    }
+
+    /// The tolerance and iteration limit of `solve`.
+    pub fn solver(&self) -> solve::Options {
+        self.globals.solver()
+    }
+
+    pub fn set_solver(&mut self, options: solve::Options) {
+        self.globals.set_solver(options);
+    }
+
+    /// Parses a whole program: one assignment per line.
+    pub fn parse(&self, source: &str) -> EngineResult<Program> {
+        Ok(Parser::new(tokenize(source)?).parse_program()?)
+    }
+
+    /// Parses a single expression, like `price * 1.2`.
+    pub fn parse_expr(&self, source: &str) -> EngineResult<Expression> {
+        Ok(Parser::new(tokenize(source)?).parse_expression()?)
+    }
+
+    /// Parses an equation, `pi * r^2 = 10`, into its two sides.
+    pub fn parse_equation(&self, source: &str) -> EngineResult<(Expression, Expression)> {
+        Ok(Parser::new(tokenize(source)?).parse_equation()?)
+    }
+
+    /// Evaluates `source`: an expression gives its value, and a program
+    /// defines its assignments and gives its result, like `-f` does.
+    pub fn eval_str(&mut self, source: &str) -> EngineResult<Value> {
+        let tokens = tokenize(source)?;
+        match is_assignment(&tokens) {
+            true => self.eval_program(Parser::new(tokens).parse_program()?),
+            false => self.eval_expr(Parser::new(tokens).parse_expression()?),
+        }
+    }
+
+    /// Runs every assignment of `program`, then gives `result`, or some
+    /// other variable when there is none.
+    pub fn eval_program(&mut self, program: Program) -> EngineResult<Value> {
+        Ok(eval::eval_program(program, &mut self.globals)?)
+    }
+
+    pub fn eval_expr(&self, expr: Expression) -> EngineResult<Value> {
+        Ok(eval::eval_expr(expr, &self.globals.env())?)
+    }
+
+    /// The derivative of `expr` with respect to `var`, simplified, as an
+    /// expression that can be printed with `to_source` or evaluated.
+    pub fn diff(&self, expr: &Expression, var: &str) -> EngineResult<Expression> {
+        Ok(diff::derivative(expr, var, self.mode())?)
+    }
+
+    /// The value of `var` that makes `lhs` equal to `rhs`, as
+    /// `solve(lhs = rhs, var)` finds it.
+    ///
+    /// ```
+    /// use simple_math::Engine;
+    ///
+    /// let engine = Engine::new();
+    /// let (area, target) = engine.parse_equation("pi * r^2 = 10").unwrap();
+    /// let r = engine.solve(area, target, "r").unwrap().to_f64();
+    /// assert!((r - 1.784124116152771).abs() < 1e-12);
+    /// ```
+    pub fn solve(&self, lhs: Expression, rhs: Expression, var: &str) -> EngineResult<Value> {
+        let span = lhs.span.to(rhs.span);
+        let equation = Expression::new(ExprKind::Operation("=".to_string(), vec![lhs, rhs]), span);
+        let var = Expression::new(ExprKind::Var(var.to_string()), span);
+        self.eval_expr(Expression::new(ExprKind::Call("solve".to_string(), vec![equation, var]), span))
+    }
+
+    /// Runs one assignment, and gives what the name is now bound to.
+    pub fn define(&mut self, assign: Assign) -> EngineResult<&Binding> {
+        let line = assign.span.line;
+        let (name, binding) = eval::eval_assign(assign, &self.globals.env())?;
+        self.globals.define(name.clone(), binding, line);
+        Ok(self.globals.get(&name).expect("just defined"))
+    }
+
+    pub fn set_var(&mut self, name: &str, value: impl Into<Value>) {
+        self.globals.insert(name.to_string(), Binding::Value(value.into()));
+    }
+
+    /// Makes `func` callable from formulas as `name`, with `arity`
+    /// arguments. It can fail with `EvalError::Host` and its own message.
+    /// It must be `Send` and `Sync`, like the engine, which may move to
+    /// another thread.
+    ///
+    /// ```
+    /// use simple_math::{Engine, Value};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register_fn("tax", 1, |args| Ok(args[0].clone() * Value::from(0.2)));
+    /// assert_eq!(engine.eval_str("tax(50)").unwrap().to_f64(), 10.);
+    /// ```
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: usize,
+        func: impl Fn(&[Value]) -> EvalResult<Value> + Send + Sync + 'static,
+    ) {
+        let host = HostFunction { arity, func: Arc::new(func) };
+        self.globals.insert(name.to_string(), Binding::Host(host));
+    }
+
+    /// Lets `resolver` supply the variables that formulas use but nothing
+    /// defines, in place of a "not defined" error. A closure taking the
+    /// name works:
+    ///
+    /// ```
+    /// use simple_math::{Engine, Value};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.set_resolver(|name: &str| (name == "eur_usd").then(|| Value::from(1.08)));
+    /// assert_eq!(engine.eval_str("100 * eur_usd").unwrap().to_f64(), 108.);
+    /// ```
+    pub fn set_resolver(&mut self, resolver: impl Resolver + 'static) {
+        self.globals.set_resolver(Box::new(resolver));
+    }
+
+    /// The value of a variable, or `None` when `name` isn't one.
+    pub fn get_var(&self, name: &str) -> Option<Value> {
+        self.globals.env().get_var(name).ok()
+    }
+
+    /// What `name` is bound to, functions included.
+    pub fn get(&self, name: &str) -> Option<&Binding> {
+        self.globals.get(name)
+    }
+
+    /// Every definition `name` has had, oldest first, with the line each
+    /// came from.
+    pub fn history(&self, name: &str) -> &[Definition] {
+        self.globals.history(name)
+    }
+
+    /// The defined names with what they are bound to now, in the order
+    /// they were first defined.
+    pub fn definitions(&self) -> impl Iterator<Item = (&str, &Definition)> {
+        self.globals.definitions()
+    }
+
+    /// The warnings raised since the last call, like a value that had to
+    /// be rounded to a float.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        self.globals.take_warnings()
+    }
+}
+
+// Hosts keep an `Engine` behind a `Mutex` or move it to a worker thread.
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    assert_send::<Engine>();
+};
@@ -1,5 +1,41 @@
 use std::{fmt, error::Error};
 
+use crate::lexer::Span;
+
+/// Lexer, parsing and eval errors are raised where the offending source
+/// range may not be known yet; whoever knows it wraps the error with
+/// `.at(span)`, and the innermost (most precise) span wins. Located errors
+/// print as `line:col: message`.
+macro_rules! impl_located {
+    ($error:ident) => {
+        impl $error {
+            /// Attaches the source range the error comes from, unless it
+            /// already has one.
+            pub fn at(self, span: Span) -> Self {
+                match self {
+                    Self::At(..) => self,
+                    e => Self::At(span, Box::new(e)),
+                }
+            }
+
+            pub fn span(&self) -> Option<Span> {
+                match self {
+                    Self::At(span, _) => Some(*span),
+                    _ => None,
+                }
+            }
+
+            /// The error without its location.
+            pub fn kind(&self) -> &Self {
+                match self {
+                    Self::At(_, e) => e.kind(),
+                    e => e,
+                }
+            }
+        }
+    };
+}
+
 // =======================================
 // =========== Lexer Error ===============
 // =======================================
@@ -8,9 +44,13 @@ use std::{fmt, error::Error};
 pub enum LexerError {
     InvalidNumber(String),
     UnrecognizedChar(char),
+    UnterminatedComment,
     ParseError(String, String), // (value, error_message)
+    At(Span, Box<LexerError>),
 }
 
+impl_located!(LexerError);
+
 impl fmt::Display for LexerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -20,9 +60,11 @@ impl fmt::Display for LexerError {
             LexerError::UnrecognizedChar(ch) => {
                 write!(f, "Couldn't recognize character '{}'", ch)
             }
+            LexerError::UnterminatedComment => write!(f, "Block comment is never closed"),
             LexerError::ParseError(value, msg) => {
                 write!(f, "Failed analysing '{}' - {}", value, msg)
             }
+            LexerError::At(span, e) => write!(f, "{}:{}: {e}", span.line, span.col),
         }
     }
 }
@@ -40,16 +82,28 @@ pub enum ParsingError {
     UnexpectedEof,
     InvalidAssignment,
     InvalidExpression(String),
+    MissingOperator(String, String), // left, right
+    AssignToConstant(String),
+    At(Span, Box<ParsingError>),
 }
 
+impl_located!(ParsingError);
+
 impl fmt::Display for ParsingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ParsingError::Unexpected(msg) => write!(f, "Unexpected - {}", msg),
-            ParsingError::Expected(e, found) => write!(f, "Expected a '{e:?}', but found '{found:?}'"),
+            ParsingError::Unexpected(msg) => write!(f, "Unexpected {}", msg),
+            ParsingError::Expected(e, found) => write!(f, "Expected {e}, but found {found}"),
             ParsingError::UnexpectedEof => write!(f, "Unexpected end of file"),
             ParsingError::InvalidAssignment => write!(f, "Invalid assignment"),
             ParsingError::InvalidExpression(msg) => write!(f, "Invalid expression - {}", msg),
+            ParsingError::MissingOperator(left, right) => {
+                write!(f, "Missing operator between {left} and {right}")
+            }
+            ParsingError::AssignToConstant(name) => {
+                write!(f, "`{name}` is a built-in constant; write `let {name} = ...` to shadow it")
+            }
+            ParsingError::At(span, e) => write!(f, "{}:{}: {e}", span.line, span.col),
         }
     }
 }
@@ -63,20 +117,174 @@ impl Error for ParsingError {}
 #[derive(Debug, Clone, PartialEq)]
 pub enum EvalError {
     VariableDoesNotExists(String),
+    FunctionDoesNotExists(String),
+    IsAFunction(String),
+    RecursionLimit(String),
     InvalidSizeOfArgsFor(String),
     UnexpectedOperator(String),
     ZeroDivisor,
+    ComplexArgument(String),
+    DimensionMismatch(String, String),
+    UnitPower(String, String), // (unit, exponent)
+    RaggedArray,
+    ShapeMismatch(String, String, String),  // (operation, left shape, right shape)
+    WrongShape(String, String, String),     // (function, expected, found)
+    SingularMatrix,
+    Host(String), // a host function's own message
+    Cycle(Vec<(String, Option<usize>)>), // (name, line): each uses the next, and the last is the first
+    Redefinition(String, usize),         // (name, line of the first definition)
+    NotDifferentiable(String),
+    ExpectedVariable(String), // the function whose second argument must be a name
+    NotConverged(String, usize),    // (variable, iterations)
+    NoSignChange(String, f64, f64), // (variable, low, high)
+    At(Span, Box<EvalError>),
 }
 
+impl_located!(EvalError);
+
 impl fmt::Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::VariableDoesNotExists(var) => write!(f, "Variable not defined: {var}"),
+            Self::FunctionDoesNotExists(name) => write!(f, "Function not defined: {name}"),
+            Self::IsAFunction(name) => write!(f, "{name} is a function, call it with {name}(...)"),
+            Self::RecursionLimit(name) => write!(f, "Too much recursion calling {name}"),
             Self::InvalidSizeOfArgsFor(op) => write!(f, "Invalid size of args for {op}"),
             Self::UnexpectedOperator(op) => write!(f, "Unexpected operator {op}"),
             Self::ZeroDivisor => write!(f, "Dividing by zero is not allowed"),
+            Self::ComplexArgument(name) => write!(f, "{name} only takes real numbers"),
+            Self::DimensionMismatch(a, b) => write!(f, "Incompatible units: {a} and {b}"),
+            Self::UnitPower(unit, exp) => write!(f, "Can't raise {unit} to the power {exp}, its exponents would not be whole numbers that fit in 32 bits"),
+            Self::RaggedArray => write!(f, "The elements of a vector or matrix must all have the same shape"),
+            Self::ShapeMismatch(op, a, b) => write!(f, "{op} can't combine {a} with {b}"),
+            Self::WrongShape(name, expected, found) => write!(f, "{name} takes {expected}, not {found}"),
+            Self::SingularMatrix => write!(f, "The matrix is singular"),
+            Self::Host(message) => write!(f, "{message}"),
+            Self::Cycle(path) => {
+                let names = path.iter().map(|(name, line)| match line {
+                    Some(line) => format!("{name} (line {line})"),
+                    None => name.clone(),
+                });
+                write!(f, "`{}` depends on itself: {}", path[0].0, names.collect::<Vec<_>>().join(" -> "))
+            }
+            Self::Redefinition(name, line) => write!(f, "`{name}` is already defined on line {line}"),
+            Self::NotDifferentiable(what) => write!(f, "Can't differentiate {what}"),
+            Self::ExpectedVariable(name) => write!(f, "`{name}` takes a variable name as its second argument"),
+            Self::NotConverged(var, n) => write!(f, "Found no value of `{var}` solving the equation in {n} iterations"),
+            Self::NoSignChange(var, low, high) => {
+                write!(f, "The two sides of the equation don't cross for `{var}` between {low} and {high}")
+            }
+            Self::At(span, e) => write!(f, "{}:{}: {e}", span.line, span.col),
         }
     }
 }
 
 impl Error for EvalError {}
+
+// =======================================
+// =========== Engine Error ==============
+// =======================================
+
+/// Whatever can go wrong running source text through `Engine`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineError {
+    Lexer(LexerError),
+    Parsing(ParsingError),
+    Eval(EvalError),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lexer(e) => write!(f, "{e}"),
+            Self::Parsing(e) => write!(f, "{e}"),
+            Self::Eval(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for EngineError {}
+
+impl From<LexerError> for EngineError {
+    fn from(e: LexerError) -> Self {
+        Self::Lexer(e)
+    }
+}
+
+impl From<ParsingError> for EngineError {
+    fn from(e: ParsingError) -> Self {
+        Self::Parsing(e)
+    }
+}
+
+impl From<EvalError> for EngineError {
+    fn from(e: EvalError) -> Self {
+        Self::Eval(e)
+    }
+}
+
+// =======================================
+// =========== Compile Error =============
+// =======================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    UndefinedVariable(String),
+    UndefinedFunction(String),
+    UnsupportedOperator(String),
+    InvalidSizeOfArgsFor(String),
+    Unsupported(String),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVariable(var) => write!(f, "Variable used before its definition: {var}"),
+            Self::UndefinedFunction(name) => write!(f, "Function not defined: {name}"),
+            Self::UnsupportedOperator(op) => write!(f, "Operator {op} can't be compiled"),
+            Self::InvalidSizeOfArgsFor(op) => write!(f, "Invalid size of args for {op}"),
+            Self::Unsupported(what) => write!(f, "{what} can't be compiled"),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+// =======================================
+// =========== Build Error ===============
+// =======================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    Compile(CompileError),
+    Io(String, String),                 // (path, error_message)
+    ToolNotFound(String, String),       // (tool, error_message)
+    ToolFailed(String, String, String), // (tool, exit_status, stderr)
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compile(e) => write!(f, "{e}"),
+            Self::Io(path, msg) => write!(f, "Couldn't write '{path}' - {msg}"),
+            Self::ToolNotFound(tool, msg) => {
+                write!(f, "Couldn't run '{tool}' - {msg}. Is it installed and in PATH?")
+            }
+            Self::ToolFailed(tool, status, stderr) => {
+                write!(f, "'{tool}' failed ({status})")?;
+                if !stderr.is_empty() {
+                    write!(f, ":\n{stderr}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+impl From<CompileError> for BuildError {
+    fn from(e: CompileError) -> Self {
+        Self::Compile(e)
+    }
+}
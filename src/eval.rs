@@ -1,57 +1,828 @@
-use crate::{ast::*, error::EvalError};
-use std::{collections::HashMap, fmt};
+use crate::{
+    ast::*,
+    bindings::{Bindings, Definition},
+    complex::{self, Complex, I},
+    constants::{Constant, constant},
+    decimal::{Context, Decimal},
+    diff::derivative,
+    error::EvalError,
+    lexer::{Number, Span},
+    matrix,
+    rational::{MAX_EXACT_BITS, MAX_EXACT_EXPONENT, Rational},
+    solve::{self, Failure, Start},
+    units::{Overflow, Unit},
+    value::{Mode, Value},
+};
+use std::{cell::RefCell, fmt, sync::Arc};
 
 pub type EvalResult<T> = Result<T, EvalError>;
 
-pub fn eval_program(tree: Program) -> EvalResult<f64> {
-    let mut vars = HashMap::new();
+/// How deep user-defined functions may call each other before evaluation
+/// gives up with `EvalError::RecursionLimit`.
+pub const MAX_CALL_DEPTH: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub params: Vec<String>,
+    pub body: Expression,
+}
+
+pub type HostFn = dyn Fn(&[Value]) -> EvalResult<Value> + Send + Sync;
+
+/// A function written in Rust, registered through `Engine::register_fn`.
+/// It gets the evaluated arguments, exactly `arity` of them.
+#[derive(Clone)]
+pub struct HostFunction {
+    pub arity: usize,
+    pub func: Arc<HostFn>,
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostFunction").field("arity", &self.arity).finish_non_exhaustive()
+    }
+}
+
+/// What a name is bound to: variables and functions share one namespace.
+#[derive(Debug, Clone)]
+pub enum Binding {
+    Value(Value),
+    Function(Function),
+    Host(HostFunction),
+}
+
+/// Supplies the variables a program uses without defining, like live
+/// exchange rates. It is asked only about names that aren't bound, and
+/// before the built-in constants, so it can shadow them. It is `Send`
+/// so that an engine using it can move to another thread.
+pub trait Resolver: Send {
+    fn resolve(&self, name: &str) -> Option<Value>;
+}
+
+impl<F: Fn(&str) -> Option<Value> + Send> Resolver for F {
+    fn resolve(&self, name: &str) -> Option<Value> {
+        self(name)
+    }
+}
+
+impl fmt::Debug for dyn Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Resolver")
+    }
+}
+
+/// Something worth telling the user about a result that still went
+/// through, like an exact value that had to be rounded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Value(v) => fmt::Display::fmt(v, f),
+            Binding::Function(func) => write!(f, "function of ({})", func.params.join(", ")),
+            Binding::Host(host) => match host.arity {
+                1 => write!(f, "host function of 1 argument"),
+                n => write!(f, "host function of {n} arguments"),
+            },
+        }
+    }
+}
+
+/// The global scope: the names a program defines, with the settings it is
+/// evaluated under and the warnings raised. Expressions see it through an
+/// `Env`, so it holds no references and can move between threads with the
+/// `Engine` that owns it.
+#[derive(Debug, Default)]
+pub struct Globals {
+    bindings: Bindings,
+    mode: Mode,
+    warnings: RefCell<Vec<Warning>>,
+    resolver: Option<Box<dyn Resolver>>,
+    solver: solve::Options,
+}
+
+impl Globals {
+    pub fn with_mode(mode: Mode) -> Self {
+        Self { mode, ..Self::default() }
+    }
+
+    /// The scope expressions are evaluated in outside any function call.
+    pub fn env(&self) -> Env<'_> {
+        Env { globals: self, params: Bindings::default(), depth: 0, mode: self.mode }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Changes how literals evaluated from now on are read. Values already
+    /// bound keep their kind.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// The warnings raised since the last call.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        self.warnings.take()
+    }
+
+    pub fn insert(&mut self, name: String, binding: Binding) {
+        self.bindings.insert(name, binding, None);
+    }
+
+    /// Like `insert`, for an assignment written on source line `line`.
+    pub fn define(&mut self, name: String, binding: Binding, line: usize) {
+        self.bindings.insert(name, binding, Some(line));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Binding> {
+        self.bindings.get(name)
+    }
+
+    /// Every definition `name` has had, oldest first.
+    pub fn history(&self, name: &str) -> &[Definition] {
+        self.bindings.history(name)
+    }
+
+    /// The names with their current definitions, in the order they were
+    /// first defined.
+    pub fn definitions(&self) -> impl Iterator<Item = (&str, &Definition)> {
+        self.bindings.iter()
+    }
+
+    /// Lets `resolver` supply the names nothing in the program binds.
+    pub fn set_resolver(&mut self, resolver: Box<dyn Resolver>) {
+        self.resolver = Some(resolver);
+    }
+
+    pub fn solver(&self) -> solve::Options {
+        self.solver
+    }
+
+    /// Sets the tolerance and iteration limit of `solve`.
+    pub fn set_solver(&mut self, options: solve::Options) {
+        self.solver = options;
+    }
+}
+
+/// Names visible to an expression: the globals and, while a function call
+/// evaluates its body, the parameters, which shadow globals without
+/// leaking into other calls. Each lives only as long as the evaluation.
+#[derive(Debug)]
+pub struct Env<'g> {
+    globals: &'g Globals,
+    params: Bindings,
+    depth: usize,
+    mode: Mode,
+}
+
+impl<'g> Env<'g> {
+    /// Evaluates what is left in this scope in `mode`.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Records a warning on the globals, once per message and span.
+    pub fn warn(&self, message: String, span: Span) {
+        let warning = Warning { message, span };
+        let mut warnings = self.globals.warnings.borrow_mut();
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+
+    pub fn solver(&self) -> solve::Options {
+        self.globals.solver
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'_ Binding> {
+        self.params.get(name).or_else(|| self.globals.get(name))
+    }
+
+    pub fn get_var(&self, name: &str) -> EvalResult<Value> {
+        match self.get(name) {
+            Some(Binding::Value(v)) => Ok(v.clone()),
+            Some(_) => Err(EvalError::IsAFunction(name.to_string())),
+            None => match self.resolve(name) {
+                Some(v) => Ok(v),
+                // unless it was given a value, `i` is the imaginary unit
+                None if name == "i" && matches!(self.mode, Mode::Complex { .. }) => Ok(Value::Complex(I)),
+                None => Err(EvalError::VariableDoesNotExists(name.to_string())),
+            },
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Option<Value> {
+        self.globals.resolver.as_ref()?.resolve(name)
+    }
+
+    fn call_scope(&self, name: &str, params: &[String], args: Vec<Value>) -> EvalResult<Env<'g>> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(EvalError::RecursionLimit(name.to_string()));
+        }
+        let mut scope = Env { globals: self.globals, params: Bindings::default(), depth: self.depth + 1, mode: self.mode };
+        for (param, arg) in params.iter().zip(args) {
+            scope.params.insert(param.clone(), Binding::Value(arg), None);
+        }
+        Ok(scope)
+    }
+}
+
+/// Runs every assignment into `globals`, then gives `result`, or some
+/// other variable when there is none.
+pub fn eval_program(tree: Program, globals: &mut Globals) -> EvalResult<Value> {
     for assign in tree.body {
-        let (name, val) = eval_assign(assign, &vars)?;
-        vars.insert(name, val);
-    }
-    match vars.get(&"result".to_string()) {
-        Some(a) => Ok(*a),
-        None => match vars.into_iter().last() {
-            Some(a) => Ok(a.1),
-            None => Ok(0.),
-        },
+        let line = assign.span.line;
+        let (name, binding) = eval_assign(assign, &globals.env())?;
+        globals.define(name, binding, line);
+    }
+    match globals.env().get_var("result") {
+        Ok(a) => Ok(a),
+        Err(_) => Ok(globals.bindings.last_value().cloned().unwrap_or_else(|| globals.mode.int(0))),
     }
 }
 
-pub fn eval_assign(a: Assign, vars: &HashMap<String, f64>) -> EvalResult<(String, f64)> {
-    let name = a.0;
-    let value = eval_expr(a.1, vars)?;
-    Ok((name, value))
+pub fn eval_assign(a: Assign, env: &Env) -> EvalResult<(String, Binding)> {
+    if a.params.is_empty() {
+        let value = eval_expr(a.expr, env).map_err(|e| e.at(a.span))?;
+        return Ok((a.name, Binding::Value(value)));
+    }
+    // Functions are evaluated when called, so their body may use names
+    // (including the function itself) that don't exist yet.
+    Ok((a.name, Binding::Function(Function { params: a.params, body: a.expr })))
 }
 
-pub fn eval_expr(e: Expression, vars: &HashMap<String, f64>) -> EvalResult<f64> {
-    match e {
-        Expression::Var(v) => match vars.get(&v) {
-            Some(i) => Ok(*i),
-            None => Err(EvalError::VariableDoesNotExists(format!("{v}"))),
-        },
-        Expression::Num(i) => Ok(i),
-        Expression::Parenthed(f) => eval_expr(*f, vars),
-        Expression::Operation(op, exprs) => eval_operation(op, exprs, vars),
+pub fn eval_expr(e: Expression, env: &Env) -> EvalResult<Value> {
+    let span = e.span;
+    match e.kind {
+        ExprKind::Var(v) => env.get_var(&v).or_else(|e| match constant(&v) {
+            Some(c) if matches!(e, EvalError::VariableDoesNotExists(_)) => Ok(constant_value(c, span, env)),
+            _ => Err(e),
+        }),
+        ExprKind::Num(n) => Ok(literal_value(&n, span, env)),
+        ExprKind::Quantity(n, unit) => Ok(literal_value(&n, span, env).with_unit(unit)),
+        ExprKind::Convert(e, unit) => map_elements(eval_expr(*e, env)?, &|v| {
+            same_dimension(&v.unit(), &unit)?;
+            Ok(v.convert(&unit))
+        }),
+        ExprKind::Parenthed(f) => eval_expr(*f, env),
+        ExprKind::Array(elements) => {
+            let mut values: Vec<Value> = Vec::with_capacity(elements.len());
+            for e in elements {
+                let span = e.span;
+                let value = eval_expr(e, env)?;
+                if values.first().is_some_and(|first| first.shape() != value.shape()) {
+                    return Err(EvalError::RaggedArray.at(span));
+                }
+                values.push(value);
+            }
+            Ok(Value::Array(values))
+        }
+        ExprKind::Operation(op, exprs) => eval_operation(op, exprs, span, env),
+        ExprKind::Call(name, args) => eval_call(name, args, span, env),
+        ExprKind::If(cond, then, otherwise) => {
+            if eval_expr(*cond, env)?.truthy() {
+                eval_expr(*then, env)
+            } else {
+                eval_expr(*otherwise, env)
+            }
+        }
+    }
+    .map_err(|err| err.at(span))
+}
+
+/// A number literal in the current mode. Exact fractions and decimals
+/// can't hold one with an exponent beyond `MAX_EXACT_EXPONENT`, nor an
+/// imaginary one, so those get a float and a warning.
+fn literal_value(n: &Number, span: Span, env: &Env) -> Value {
+    let value = env.mode.literal(n);
+    if matches!(value, Value::Float(_) | Value::Complex(_)) && matches!(env.mode, Mode::Exact | Mode::Decimal(_)) {
+        warn_float_fallback(&format!("`{n}`"), &[env.mode.int(0)], span, env);
+    }
+    value
+}
+
+/// A built-in constant in the current mode. Exact fractions can't hold
+/// any of them, nor decimals an infinity, so those get a float and a
+/// warning.
+fn constant_value(c: &Constant, span: Span, env: &Env) -> Value {
+    match env.mode {
+        Mode::Complex { .. } => return Value::Complex(Complex::real(c.value)),
+        Mode::Decimal(ctx) => {
+            if let Some(d) = c.decimal(ctx) {
+                return Value::Decimal(d, ctx);
+            }
+        }
+        _ => {}
+    }
+    // the mode's own integers stand for its precision
+    warn_float_fallback(&format!("`{}`", c.name), &[env.mode.int(0)], span, env);
+    Value::Float(c.value)
+}
+
+/// `span` covers the whole call, for warnings.
+pub fn eval_call(name: String, args: Vec<Expression>, span: Span, env: &Env) -> EvalResult<Value> {
+    if name == "diff" && env.get(&name).is_none() {
+        return eval_diff(args, env);
+    }
+    if name == "solve" && is_equation(&args) && env.get(&name).is_none() {
+        return eval_solve(args, span, env);
+    }
+    let args = args
+        .into_iter()
+        .map(|a| eval_expr(a, env))
+        .collect::<EvalResult<Vec<Value>>>()?;
+
+    match env.get(&name) {
+        Some(Binding::Function(func)) => {
+            if func.params.len() != args.len() {
+                return Err(EvalError::InvalidSizeOfArgsFor(name));
+            }
+            let scope = env.call_scope(&name, &func.params, args)?;
+            eval_expr(func.body.clone(), &scope)
+        }
+        Some(Binding::Host(host)) => {
+            if host.arity != args.len() {
+                return Err(EvalError::InvalidSizeOfArgsFor(name));
+            }
+            (host.func)(&args)
+        }
+        _ => {
+            if let Some((arity, func)) = matrix::builtin(&name) {
+                if args.len() != arity {
+                    return Err(EvalError::InvalidSizeOfArgsFor(name));
+                }
+                return func(args, env.mode);
+            }
+            eval_elementwise(name, args, span, env)
+        }
+    }
+}
+
+/// `diff(expr, x)`: the derivative of `expr` with respect to `x`, at the
+/// value `x` has now.
+fn eval_diff(args: Vec<Expression>, env: &Env) -> EvalResult<Value> {
+    let [expr, var] = <[Expression; 2]>::try_from(args).map_err(|_| EvalError::InvalidSizeOfArgsFor("diff".to_string()))?;
+    let ExprKind::Var(x) = &var.kind else {
+        return Err(EvalError::ExpectedVariable("diff".to_string()).at(var.span));
+    };
+    eval_expr(derivative(&expr, x, env.mode)?, env)
+}
+
+/// `solve(lhs = rhs, x)`: the value of `x` that makes both sides equal,
+/// searching outward from the value `x` has now, or from 1. A third
+/// argument is where to start instead, and a third and a fourth are a range
+/// the answer lies in. The answer keeps the unit `x` has.
+fn eval_solve(args: Vec<Expression>, span: Span, env: &Env) -> EvalResult<Value> {
+    let invalid = || EvalError::InvalidSizeOfArgsFor("solve".to_string());
+    let mut args = args.into_iter();
+    let (Some(equation), Some(var)) = (args.next(), args.next()) else {
+        return Err(invalid());
+    };
+    let ExprKind::Var(x) = var.kind else {
+        return Err(EvalError::ExpectedVariable("solve".to_string()).at(var.span));
+    };
+    let ExprKind::Operation(_, sides) = equation.kind else { unreachable!("checked to be an equation") };
+    let difference = Expression::new(ExprKind::Operation("-".to_string(), sides), equation.span);
+
+    let current = env.get_var(&x).ok().filter(|v| v.to_f64().is_finite());
+    let unit = current.as_ref().map(Value::unit).unwrap_or_default();
+    let bounds = args.map(|a| eval_expr(a, env).map(|v| v.convert(&unit).to_f64())).collect::<EvalResult<Vec<_>>>()?;
+    let start = match bounds[..] {
+        [] => Start::Guess(current.map_or(1., |v| v.to_f64())),
+        [guess] => Start::Guess(guess),
+        [low, high] => Start::Range(low, high),
+        _ => return Err(invalid()),
+    };
+
+    let at = |e: &Expression, v: f64| -> EvalResult<f64> {
+        let value = Value::Float(v).with_unit(unit.clone());
+        let mut scope = env.call_scope("solve", std::slice::from_ref(&x), vec![value])?;
+        scope.set_mode(Mode::Float);
+        Ok(eval_expr(e.clone(), &scope)?.to_f64())
+    };
+    let first = match start {
+        Start::Guess(v) | Start::Range(v, _) => v,
+    };
+    // mistakes like an undefined name show up here, rather than as no answer
+    at(&difference, first)?;
+    let f = |v: f64| at(&difference, v).ok().filter(|y| y.is_finite());
+    // units may scale the derivative differently than the difference
+    let derivative = derivative(&difference, &x, Mode::Float).ok().filter(|_| unit.is_none());
+    let slope = derivative.map(|d| move |v: f64| at(&d, v).ok().filter(|y| y.is_finite()));
+
+    let options = env.solver();
+    match solve::root(&f, slope.as_ref().map(|s| s as &solve::Function), start, options) {
+        Ok(root) => {
+            warn_float_fallback("`solve`", &[env.mode.int(0)], span, env);
+            let root = match env.mode {
+                Mode::Complex { .. } => Value::Complex(Complex::real(root)),
+                _ => Value::Float(root),
+            };
+            Ok(root.with_unit(unit))
+        }
+        Err(Failure::NoSignChange) => {
+            let Start::Range(low, high) = start else { unreachable!("only ranges are checked for a sign change") };
+            Err(EvalError::NoSignChange(x, low, high).at(equation.span))
+        }
+        Err(Failure::NotConverged) => Err(EvalError::NotConverged(x, options.max_iterations).at(equation.span)),
+    }
+}
+
+/// Calls a builtin on each element of its array arguments, pairing them
+/// like the arithmetic operators do. `min` and `max` of a single array
+/// give its smallest and largest element.
+fn eval_elementwise(name: String, args: Vec<Value>, span: Span, env: &Env) -> EvalResult<Value> {
+    let widest = args.iter().map(Value::shape).max_by_key(Vec::len).unwrap_or_default();
+    if widest.is_empty() {
+        return match args.iter().any(|a| matches!(a, Value::Quantity(..))) {
+            true => eval_unit_builtin(name, args, span, env),
+            false => eval_builtin(name, args, span, env),
+        };
+    }
+    if matches!(name.as_str(), "min" | "max") && args.len() == 1 {
+        return eval_elementwise(name, flatten(args), span, env);
+    }
+    if let Some(arg) = args.iter().find(|a| !widest.ends_with(&a.shape())) {
+        return Err(EvalError::ShapeMismatch(format!("`{name}`"), matrix::describe(&widest), matrix::describe(&arg.shape())));
+    }
+    (0..widest[0])
+        .map(|i| {
+            let args = args
+                .iter()
+                .map(|a| match a {
+                    Value::Array(elements) if a.shape().len() == widest.len() => elements[i].clone(),
+                    a => a.clone(),
+                })
+                .collect();
+            eval_elementwise(name.clone(), args, span, env)
+        })
+        .collect::<EvalResult<Vec<Value>>>()
+        .map(Value::Array)
+}
+
+/// The numbers in `values`, taking arrays apart.
+fn flatten(values: Vec<Value>) -> Vec<Value> {
+    values
+        .into_iter()
+        .flat_map(|v| match v {
+            Value::Array(elements) => flatten(elements),
+            v => vec![v],
+        })
+        .collect()
+}
+
+/// Applies `f` to `a` and `b`, or to pairs of their elements when either
+/// is an array. An array with fewer dimensions pairs with each row of the
+/// other, so a matrix plus a vector adds the vector to every row, and a
+/// number pairs with every element.
+pub fn broadcast(op: &str, a: Value, b: Value, f: &dyn Fn(Value, Value) -> EvalResult<Value>) -> EvalResult<Value> {
+    let (sa, sb) = (a.shape(), b.shape());
+    if !sa.ends_with(&sb) && !sb.ends_with(&sa) {
+        return Err(EvalError::ShapeMismatch(format!("`{op}`"), matrix::describe(&sa), matrix::describe(&sb)));
+    }
+    pairwise(a, b, f)
+}
+
+fn pairwise(a: Value, b: Value, f: &dyn Fn(Value, Value) -> EvalResult<Value>) -> EvalResult<Value> {
+    let (da, db) = (a.shape().len(), b.shape().len());
+    let elements: EvalResult<Vec<Value>> = match (a, b) {
+        (Value::Array(x), Value::Array(y)) if da == db => x.into_iter().zip(y).map(|(x, y)| pairwise(x, y, f)).collect(),
+        (Value::Array(x), b) if da > db => x.into_iter().map(|x| pairwise(x, b.clone(), f)).collect(),
+        (a, Value::Array(y)) => y.into_iter().map(|y| pairwise(a.clone(), y, f)).collect(),
+        (a, b) => return f(a, b),
+    };
+    elements.map(Value::Array)
+}
+
+/// Applies `f` to `v`, or to each of its elements when it is an array.
+pub fn map_elements(v: Value, f: &dyn Fn(Value) -> EvalResult<Value>) -> EvalResult<Value> {
+    match v {
+        Value::Array(elements) => elements.into_iter().map(|e| map_elements(e, f)).collect::<EvalResult<_>>().map(Value::Array),
+        v => f(v),
+    }
+}
+
+fn eval_builtin(name: String, args: Vec<Value>, span: Span, env: &Env) -> EvalResult<Value> {
+    let wants_complex = matches!(env.mode, Mode::Complex { .. }) || args.iter().any(|a| matches!(a, Value::Complex(_)));
+    if let Some((arity, func)) = complex::builtin(&name)
+        && (wants_complex || builtin(&name).is_none())
+    {
+        if args.len() != arity {
+            return Err(EvalError::InvalidSizeOfArgsFor(name));
+        }
+        let args = args.iter().map(Value::to_complex).collect::<Vec<_>>();
+        return Ok(Value::Complex(func(&args)));
+    }
+    if args.iter().any(Value::is_nonreal) {
+        return Err(EvalError::ComplexArgument(format!("`{name}`")));
+    }
+    let builtin = builtin(&name).ok_or_else(|| EvalError::FunctionDoesNotExists(name.clone()))?;
+    if !builtin.arity.accepts(args.len()) {
+        return Err(EvalError::InvalidSizeOfArgsFor(name));
+    }
+    if let Some(result) = precise_builtin(&name, &args) {
+        return Ok(result);
+    }
+    warn_float_fallback(&format!("`{name}`"), &args, span, env);
+    let args = args.iter().map(Value::to_f64).collect::<Vec<f64>>();
+    Ok(Value::Float((builtin.func)(&args)))
+}
+
+/// Builtins given a quantity. Those that keep the unit, like `abs` or
+/// `max`, work in the first argument's unit, `sqrt` takes the unit's
+/// root, and the rest only take plain numbers.
+fn eval_unit_builtin(name: String, args: Vec<Value>, span: Span, env: &Env) -> EvalResult<Value> {
+    let unit = args[0].unit();
+    match name.as_str() {
+        "sqrt" if args.len() == 1 => {
+            let (arg, root) = unit_power(args[0].clone(), 0.5)?;
+            Ok(eval_builtin(name, vec![arg.magnitude()], span, env)?.with_unit(root))
+        }
+        "abs" | "floor" | "ceil" | "round" | "min" | "max" | "hypot" | "re" | "im" | "conj" => {
+            let args = args
+                .into_iter()
+                .map(|a| {
+                    same_dimension(&a.unit(), &unit)?;
+                    Ok(a.convert(&unit).magnitude())
+                })
+                .collect::<EvalResult<Vec<Value>>>()?;
+            Ok(eval_builtin(name, args, span, env)?.with_unit(unit))
+        }
+        _ => {
+            let quantity = args.iter().find(|a| !a.unit().is_none()).expect("some argument has a unit");
+            Err(EvalError::DimensionMismatch(unit_name(&quantity.unit()), unit_name(&Unit::default())))
+        }
+    }
+}
+
+/// `DimensionMismatch` unless `a` and `b` measure the same kind of thing.
+pub fn same_dimension(a: &Unit, b: &Unit) -> EvalResult<()> {
+    match a.dimension() == b.dimension() {
+        true => Ok(()),
+        false => Err(EvalError::DimensionMismatch(unit_name(a), unit_name(b))),
+    }
+}
+
+/// `a * b`, or `UnitPower` when an exponent of its unit wouldn't fit.
+pub fn multiply(a: Value, b: Value) -> EvalResult<Value> {
+    let (ua, ub) = (a.unit(), b.unit());
+    ua.aligned(&ub).and_then(|aligned| ua.mul(&aligned)).map_err(too_large)?;
+    Ok(a * b)
+}
+
+/// `a / b`, or `UnitPower` when an exponent of its unit wouldn't fit.
+/// `b` must not be zero.
+pub fn divide(a: Value, b: Value) -> EvalResult<Value> {
+    let (ua, ub) = (a.unit(), b.unit());
+    ua.aligned(&ub).and_then(|aligned| ua.div(&aligned)).map_err(too_large)?;
+    Ok(a / b)
+}
+
+fn too_large(Overflow(name, exp): Overflow) -> EvalError {
+    EvalError::UnitPower(format!("`{name}`"), exp.to_string())
+}
+
+fn unit_name(unit: &Unit) -> String {
+    match unit.is_none() {
+        true => "a plain number".to_string(),
+        false => format!("`{unit}`"),
+    }
+}
+
+/// `base` in a unit that can be raised to `exp`, and the unit of the
+/// result. The square root of `m*km` is taken in `m^2`, giving `m`.
+fn unit_power(base: Value, exp: f64) -> EvalResult<(Value, Unit)> {
+    let unit = base.unit();
+    if let Some(power) = unit.pow(exp) {
+        return Ok((base, power));
+    }
+    let si = Unit::base(unit.dimension());
+    match si.pow(exp) {
+        Some(power) => Ok((base.convert(&si), power)),
+        None => Err(EvalError::UnitPower(unit_name(&unit), exp.to_string())),
     }
 }
 
-pub fn eval_operation(op: String, exprs: Vec<Expression>, vars: &HashMap<String, f64>) -> EvalResult<f64> {
+/// A builtin computed without going through floats, when every argument
+/// is exact or decimal and the builtin supports it.
+fn precise_builtin(name: &str, args: &[Value]) -> Option<Value> {
+    if let Some(ctx) = args.iter().filter_map(Value::decimal_context).reduce(Context::wider) {
+        let args = args.iter().map(|a| a.to_decimal(ctx)).collect::<Option<Vec<Decimal>>>()?;
+        return decimal_builtin(name, &args, ctx).map(|d| Value::Decimal(d, ctx));
+    }
+    let args = args
+        .iter()
+        .map(|a| match a {
+            Value::Exact(r) => Some(r.clone()),
+            _ => None,
+        })
+        .collect::<Option<Vec<Rational>>>()?;
+    exact_builtin(name, &args).map(Value::Exact)
+}
+
+/// Tells that `what` was computed in floating point although some of
+/// `args` were more precise.
+fn warn_float_fallback(what: &str, args: &[Value], span: Span, env: &Env) {
+    if let Some(precision) = args.iter().find_map(Value::precision_name) {
+        env.warn(format!("{what} has no {precision} result here, so it was computed in floating point"), span);
+    }
+}
+
+/// The builtins that keep fractions exact, when they can.
+fn exact_builtin(name: &str, args: &[Rational]) -> Option<Rational> {
+    match name {
+        "abs" => Some(args[0].abs()),
+        "floor" => Some(args[0].floor()),
+        "ceil" => Some(args[0].ceil()),
+        "round" => Some(args[0].round()),
+        "sqrt" => args[0].sqrt(),
+        "min" => args.iter().min().cloned(),
+        "max" => args.iter().max().cloned(),
+        _ => None,
+    }
+}
+
+/// The builtins computed to the context's precision.
+fn decimal_builtin(name: &str, args: &[Decimal], ctx: Context) -> Option<Decimal> {
+    match name {
+        "sqrt" => ctx.sqrt(&args[0]),
+        "exp" => ctx.exp(&args[0]),
+        "ln" => ctx.ln(&args[0]),
+        "log10" => ctx.div(&ctx.ln(&args[0])?, &ctx.ln(&Decimal::from_int(10))?),
+        "log" => ctx.div(&ctx.ln(&args[1])?, &ctx.ln(&args[0])?),
+        "abs" => Some(args[0].abs()),
+        "floor" => Some(args[0].floor()),
+        "ceil" => Some(args[0].ceil()),
+        "round" => Some(args[0].round()),
+        "min" => args.iter().min().cloned(),
+        "max" => args.iter().max().cloned(),
+        "hypot" => ctx.sqrt(&ctx.add(&ctx.mul(&args[0], &args[0]), &ctx.mul(&args[1], &args[1]))),
+        _ => None,
+    }
+}
+
+fn eval_pow(base: Value, exp: Value, span: Span, env: &Env) -> EvalResult<Value> {
+    same_dimension(&exp.unit(), &Unit::default())?;
+    if let Value::Quantity(..) = base {
+        if exp.is_nonreal() {
+            return Err(EvalError::UnitPower(unit_name(&base.unit()), exp.to_string()));
+        }
+        let (base, unit) = unit_power(base, exp.to_f64())?;
+        return Ok(eval_pow(base.magnitude(), exp, span, env)?.with_unit(unit));
+    }
+    if let (Value::Complex(_), _) | (_, Value::Complex(_)) = (&base, &exp) {
+        return Ok(Value::Complex(base.to_complex().pow(exp.to_complex())));
+    }
+    if let (Value::Exact(b), Value::Exact(e)) = (&base, &exp)
+        && e.is_integer()
+        && let Some(e) = e.numer().to_i64().filter(|e| e.abs() <= MAX_EXACT_EXPONENT)
+        && b.pow_bits(e) <= MAX_EXACT_BITS
+    {
+        return b.pow(e).map(Value::Exact).ok_or(EvalError::ZeroDivisor);
+    }
+    if let Some(ctx) = base.decimal_context().into_iter().chain(exp.decimal_context()).reduce(Context::wider)
+        && let (Some(b), Some(e)) = (base.to_decimal(ctx), exp.to_decimal(ctx))
+    {
+        if b.is_zero() && e.is_negative() {
+            return Err(EvalError::ZeroDivisor);
+        }
+        if let Some(result) = ctx.pow(&b, &e) {
+            return Ok(Value::Decimal(result, ctx));
+        }
+    }
+    warn_float_fallback("this power", &[base.clone(), exp.clone()], span, env);
+    Ok(Value::Float(base.to_f64().powf(exp.to_f64())))
+}
+
+/// `span` covers the whole operation, for warnings.
+pub fn eval_operation(op: String, exprs: Vec<Expression>, span: Span, env: &Env) -> EvalResult<Value> {
     match op.as_str() {
         "+" => match exprs.len() {
-            1 => eval_expr(exprs[0].clone(), vars),
-            2 => Ok(eval_expr(exprs[0].clone(), vars)? + eval_expr(exprs[1].clone(), vars)?),
+            1 => eval_expr(exprs[0].clone(), env),
+            2 => {
+                let (a, b) = (eval_expr(exprs[0].clone(), env)?, eval_expr(exprs[1].clone(), env)?);
+                broadcast("+", a, b, &|a, b| {
+                    same_dimension(&a.unit(), &b.unit())?;
+                    Ok(a + b)
+                })
+            }
             _ => Err(EvalError::InvalidSizeOfArgsFor("+".to_string())),
         },
         "-" => match exprs.len() {
-            1 => Ok(-(eval_expr(exprs[0].clone(), vars)?)),
-            2 => Ok(eval_expr(exprs[0].clone(), vars)? - eval_expr(exprs[1].clone(), vars)?),
+            1 => Ok(-(eval_expr(exprs[0].clone(), env)?)),
+            2 => {
+                let (a, b) = (eval_expr(exprs[0].clone(), env)?, eval_expr(exprs[1].clone(), env)?);
+                broadcast("-", a, b, &|a, b| {
+                    same_dimension(&a.unit(), &b.unit())?;
+                    Ok(a - b)
+                })
+            }
             _ => Err(EvalError::InvalidSizeOfArgsFor("-".to_string())),
         },
-        "*" => Ok(eval_expr(exprs[0].clone(), vars)? * eval_expr(exprs[1].clone(), vars)?),
-        "/" => match eval_expr(exprs[1].clone(), vars) { 
-            Ok(0.0) => Err(EvalError::ZeroDivisor),
-            _ => Ok(eval_expr(exprs[0].clone(), vars)? / eval_expr(exprs[1].clone(), vars)?) },
-        _ => Err(EvalError::UnexpectedOperator(format!("{op}"))),
+        "*" => broadcast("*", eval_expr(exprs[0].clone(), env)?, eval_expr(exprs[1].clone(), env)?, &multiply),
+        "/" => {
+            let divisor_span = exprs[1].span;
+            let (a, b) = (eval_expr(exprs[0].clone(), env)?, eval_expr(exprs[1].clone(), env)?);
+            broadcast("/", a, b, &|a, d| match d.is_zero() {
+                true => Err(EvalError::ZeroDivisor.at(divisor_span)),
+                false => divide(a, d),
+            })
+        }
+        "^" => {
+            let (a, b) = (eval_expr(exprs[0].clone(), env)?, eval_expr(exprs[1].clone(), env)?);
+            broadcast("^", a, b, &|a, b| eval_pow(a, b, span, env))
+        }
+        "@" => matrix::matmul(eval_expr(exprs[0].clone(), env)?, eval_expr(exprs[1].clone(), env)?),
+        "!" => map_elements(eval_expr(exprs[0].clone(), env)?, &|v| Ok(from_bool(!v.truthy(), env))),
+        "&&" => Ok(from_bool(
+            eval_expr(exprs[0].clone(), env)?.truthy() && eval_expr(exprs[1].clone(), env)?.truthy(),
+            env,
+        )),
+        "||" => Ok(from_bool(
+            eval_expr(exprs[0].clone(), env)?.truthy() || eval_expr(exprs[1].clone(), env)?.truthy(),
+            env,
+        )),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => {
+            let a = eval_expr(exprs[0].clone(), env)?;
+            let b = eval_expr(exprs[1].clone(), env)?;
+            broadcast(&op, a, b, &|a, b| {
+                if !matches!(op.as_str(), "==" | "!=") && (a.is_nonreal() || b.is_nonreal()) {
+                    return Err(EvalError::ComplexArgument(format!("`{op}`")));
+                }
+                same_dimension(&a.unit(), &b.unit())?;
+                Ok(from_bool(match op.as_str() {
+                    "==" => a == b,
+                    "!=" => a != b,
+                    "<" => a < b,
+                    "<=" => a <= b,
+                    ">" => a > b,
+                    _ => a >= b,
+                }, env))
+            })
+        }
+        _ => Err(EvalError::UnexpectedOperator(op.to_string())),
+    }
+}
+
+/// Comparisons and logic operators give 1 for true and 0 for false.
+pub fn from_bool(b: bool, env: &Env) -> Value {
+    env.mode.int(if b { 1 } else { 0 })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            Self::Exact(a) => n == a,
+            Self::AtLeast(a) => n >= a,
+        }
     }
 }
+
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub func: fn(&[f64]) -> f64,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin { name: "sqrt", arity: Arity::Exact(1), func: |a| a[0].sqrt() },
+    Builtin { name: "abs", arity: Arity::Exact(1), func: |a| a[0].abs() },
+    Builtin { name: "floor", arity: Arity::Exact(1), func: |a| a[0].floor() },
+    Builtin { name: "ceil", arity: Arity::Exact(1), func: |a| a[0].ceil() },
+    Builtin { name: "round", arity: Arity::Exact(1), func: |a| a[0].round() },
+    Builtin { name: "exp", arity: Arity::Exact(1), func: |a| a[0].exp() },
+    Builtin { name: "ln", arity: Arity::Exact(1), func: |a| a[0].ln() },
+    Builtin { name: "log10", arity: Arity::Exact(1), func: |a| a[0].log10() },
+    Builtin { name: "log", arity: Arity::Exact(2), func: |a| a[1].log(a[0]) },
+    Builtin { name: "sin", arity: Arity::Exact(1), func: |a| a[0].sin() },
+    Builtin { name: "cos", arity: Arity::Exact(1), func: |a| a[0].cos() },
+    Builtin { name: "tan", arity: Arity::Exact(1), func: |a| a[0].tan() },
+    Builtin { name: "asin", arity: Arity::Exact(1), func: |a| a[0].asin() },
+    Builtin { name: "acos", arity: Arity::Exact(1), func: |a| a[0].acos() },
+    Builtin { name: "atan", arity: Arity::Exact(1), func: |a| a[0].atan() },
+    Builtin { name: "atan2", arity: Arity::Exact(2), func: |a| a[0].atan2(a[1]) },
+    Builtin { name: "sinh", arity: Arity::Exact(1), func: |a| a[0].sinh() },
+    Builtin { name: "cosh", arity: Arity::Exact(1), func: |a| a[0].cosh() },
+    Builtin { name: "tanh", arity: Arity::Exact(1), func: |a| a[0].tanh() },
+    Builtin { name: "asinh", arity: Arity::Exact(1), func: |a| a[0].asinh() },
+    Builtin { name: "acosh", arity: Arity::Exact(1), func: |a| a[0].acosh() },
+    Builtin { name: "atanh", arity: Arity::Exact(1), func: |a| a[0].atanh() },
+    Builtin { name: "min", arity: Arity::AtLeast(1), func: |a| a.iter().copied().fold(f64::INFINITY, f64::min) },
+    Builtin { name: "max", arity: Arity::AtLeast(1), func: |a| a.iter().copied().fold(f64::NEG_INFINITY, f64::max) },
+    Builtin { name: "hypot", arity: Arity::Exact(2), func: |a| a[0].hypot(a[1]) },
+];
+
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
@@ -5,35 +5,268 @@ pub struct Lexer {
     text: String,
     pos: usize,
     current_line: usize,
+    line_start: usize,
 }
 
 impl Lexer {
     pub fn new(text: &str) -> Self {
-        let mut text = String::from(text);
-        text = text.replace(" ", "");
-        text = text.replace("\t", "");
         Self {
-            text,
+            text: String::from(text),
             pos: 0,
-            current_line: 0,
+            current_line: 1,
+            line_start: 0,
+        }
+    }
+
+    /// Skips whitespace and comments, returning the comments. Whitespace is
+    /// skipped here rather than stripped up front, so that keywords like
+    /// `if a then b` stay separate from their neighbours and spans point
+    /// into the original text. Newlines are tokens, so they are not skipped
+    /// except inside block comments.
+    fn skip_trivia(&mut self) -> LexResult<Vec<Comment>> {
+        let mut trivia = vec![];
+        loop {
+            let rest = &self.text[self.pos..];
+            self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\r']).len();
+            let rest = &self.text[self.pos..];
+            let start = self.pos;
+
+            if rest.starts_with('#') {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else if rest.starts_with("/*") {
+                let (line, line_start) = (self.current_line, self.line_start);
+                let mut depth = 0;
+                loop {
+                    let rest = &self.text[self.pos..];
+                    if rest.starts_with("/*") {
+                        depth += 1;
+                        self.pos += 2;
+                    } else if rest.starts_with("*/") {
+                        depth -= 1;
+                        self.pos += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else if let Some(ch) = rest.chars().next() {
+                        self.pos += ch.len_utf8();
+                        if ch == '\n' {
+                            self.current_line += 1;
+                            self.line_start = self.pos;
+                        }
+                    } else {
+                        let opening = Span {
+                            start,
+                            end: start + 2,
+                            line,
+                            col: self.text[line_start..start].chars().count() + 1,
+                        };
+                        return Err(LexerError::UnterminatedComment.at(opening));
+                    }
+                }
+                let span = Span {
+                    start,
+                    end: self.pos,
+                    line,
+                    col: self.text[line_start..start].chars().count() + 1,
+                };
+                trivia.push(Comment {
+                    text: self.text[start..self.pos].to_string(),
+                    span,
+                });
+                continue;
+            } else {
+                return Ok(trivia);
+            }
+
+            trivia.push(Comment {
+                text: self.text[start..self.pos].to_string(),
+                span: self.span_from(start),
+            });
+        }
+    }
+
+    /// Lexes the number literal starting at byte `start`: decimals like
+    /// `1_000.5` or `6.022e23`, and integers in base 16, 2 or 8 written
+    /// `0xff`, `0b1010` and `0o17`.
+    fn lex_number(&mut self, start: usize) -> LexResult<Number> {
+        self.pos = start;
+        let prefix = self.text.get(start..start + 2).unwrap_or("").to_string();
+        let radix = match prefix.as_str() {
+            "0x" | "0X" => 16,
+            "0b" | "0B" => 2,
+            "0o" | "0O" => 8,
+            _ => 10,
+        };
+
+        let mut buf = String::new();
+        if radix != 10 {
+            self.pos += 2;
+            if self.lex_digits(radix, &mut buf)? == 0 {
+                return Err(self.invalid_number(format!("expected base {radix} digits after `{prefix}`")));
+            }
+            if let Some(c) = self.peek_char().filter(|c| c.is_alphanumeric() || *c == '.') {
+                return Err(self.invalid_number(format!("`{c}` is not a base {radix} digit")));
+            }
+            // exact up to 2^53, like any other literal after that
+            let value = buf.chars().fold(0., |n, d| n * radix as f64 + d.to_digit(radix).unwrap() as f64);
+            return Ok(Number { value, text: format!("{prefix}{buf}"), imaginary: false });
+        }
+
+        let mut digits = self.lex_digits(10, &mut buf)?;
+        if self.peek_char() == Some('.') {
+            buf.push('.');
+            self.pos += 1;
+            digits += self.lex_digits(10, &mut buf)?;
+        }
+        if digits == 0 {
+            self.pos = start;
+            return Err(self.invalid_number("expected digits around `.`".to_string()));
+        }
+        if self.peek_char() == Some('.') {
+            return Err(self.invalid_number("a number can only have one decimal point".to_string()));
+        }
+
+        if let Some(e @ ('e' | 'E')) = self.peek_char() {
+            buf.push(e);
+            self.pos += 1;
+            if let Some(sign @ ('+' | '-')) = self.peek_char() {
+                buf.push(sign);
+                self.pos += 1;
+            }
+            if self.lex_digits(10, &mut buf)? == 0 {
+                return Err(self.invalid_number("expected the exponent's digits, as in 1e-3".to_string()));
+            }
+            if self.peek_char() == Some('.') {
+                return Err(self.invalid_number("the exponent must be a whole number".to_string()));
+            }
+        }
+
+        // `3i` is imaginary, `3in` a number next to a name
+        let rest = &self.text[self.pos..];
+        let imaginary = rest.starts_with('i') && !rest[1..].starts_with(|c: char| c.is_alphanumeric() || c == '_');
+        if imaginary {
+            self.pos += 1;
+        }
+
+        match buf.parse::<f64>() {
+            Ok(value) => Ok(Number { value, text: buf, imaginary }),
+            Err(e) => Err(LexerError::ParseError(buf, format!("could not read the number: {e}")).at(self.span_from(start))),
+        }
+    }
+
+    /// Reads digits of `radix` into `buf`, skipping `_` separators, which
+    /// may only sit between two digits. Returns how many digits were read.
+    fn lex_digits(&mut self, radix: u32, buf: &mut String) -> LexResult<usize> {
+        let mut count = 0;
+        while let Some(c) = self.peek_char() {
+            if c.is_digit(radix) {
+                buf.push(c);
+                count += 1;
+            } else if c == '_' {
+                let digit_after = self.text[self.pos + 1..].starts_with(|c: char| c.is_digit(radix));
+                if count == 0 || !digit_after {
+                    return Err(self.invalid_number("`_` may only separate two digits, as in 1_000".to_string()));
+                }
+            } else {
+                break;
+            }
+            self.pos += 1;
+        }
+        Ok(count)
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    /// `InvalidNumber` pointing at the character under the cursor.
+    fn invalid_number(&mut self, msg: String) -> LexerError {
+        let start = self.pos;
+        self.pos += self.peek_char().map_or(0, char::len_utf8);
+        LexerError::InvalidNumber(msg).at(self.span_from(start))
+    }
+
+    /// Span from byte `start` on the current line up to the current position.
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: self.pos,
+            line: self.current_line,
+            col: self.text[self.line_start..start].chars().count() + 1,
+        }
+    }
+}
+
+/// A range of source text. `start` and `end` are byte offsets, `line` and
+/// `col` are 1-based and point at `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        if other.start < self.start {
+            return other.to(self);
+        }
+        Span {
+            end: self.end.max(other.end),
+            ..self
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
-    pub line: usize,
+    pub span: Span,
     pub token_type: TokenType,
+    /// Comments right before this token. The parser ignores them, but
+    /// they are kept so a formatter can put them back.
+    pub trivia: Vec<Comment>,
+}
+
+/// A `# line` or `/* block */` comment, `text` including its delimiters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+/// A number literal: its value as a float, and its text without `_`
+/// separators, for modes that read it more precisely. An imaginary
+/// literal like `3i` has the text `3` and `imaginary` set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Number {
+    pub value: f64,
+    pub text: String,
+    pub imaginary: bool,
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.text, if self.imaginary { "i" } else { "" })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
-    Number(f64),
+    Number(Number),
     LParen,
     RParen,
+    LBracket,
+    RBracket,
+    Comma,
     Op(String),
     Assign,
     Ident(String),
+    Let,
+    If,
+    Then,
+    Else,
     EndExpr,
 }
 
@@ -43,24 +276,41 @@ impl fmt::Display for TokenType {
             Self::Number(n) => write!(f,"{n}"),
             Self::LParen => write!(f,"("),
             Self::RParen => write!(f,")"),
+            Self::LBracket => write!(f,"["),
+            Self::RBracket => write!(f,"]"),
+            Self::Comma => write!(f,","),
             Self::Op(s) => write!(f,"{s}"),
             Self::Assign => write!(f,"="),
             Self::Ident(s) => write!(f,"{s}"),
-            Self::EndExpr => write!(f,"$")
+            Self::Let => write!(f,"let"),
+            Self::If => write!(f,"if"),
+            Self::Then => write!(f,"then"),
+            Self::Else => write!(f,"else"),
+            Self::EndExpr => write!(f,"end of line")
         }
     }
 }
 
-pub fn binding_power(op: &str) -> (f32, f32) {
-    match op {
+/// The binding powers of a binary operator, or `None` for one that is
+/// only unary, like `!`.
+pub fn binding_power(op: &str) -> Option<(f32, f32)> {
+    let bp = match op {
+        "||" => (0.2, 0.3),
+        "&&" => (0.4, 0.5),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => (0.6, 0.7),
         "+" | "-" => (1., 1.1),
-        "*" | "/" => (2., 2.1),
-        _ => (3., 3.1),
-    }
+        "*" | "/" | "@" => (2., 2.1),
+        // right associative and tighter than unary minus: -2^2 == -(2^2)
+        "^" => (4.1, 4.),
+        // only found in the equation given to `solve`
+        "=" => (0., 0.1),
+        _ => return None,
+    };
+    Some(bp)
 }
 
 pub fn is_valid_unary(op: &str) -> bool {
-    let valid = vec!["+", "-", "!"];
+    let valid = ["+", "-", "!"];
     valid.contains(&op)
 }
 
@@ -71,14 +321,55 @@ pub fn unary_binding_power(op: &str) -> (f32, f32) {
     }
 }
 
+/// Whether `tokens` assign a name, rather than being an expression with
+/// an `=` only inside brackets, like `solve(x^2 = 2, x)`.
+pub fn is_assignment(tokens: &[Token]) -> bool {
+    let mut depth = 0;
+    tokens.iter().any(|t| {
+        match t.token_type {
+            TokenType::LParen | TokenType::LBracket => depth += 1,
+            TokenType::RParen | TokenType::RBracket => depth -= 1,
+            _ => {}
+        }
+        t.token_type == TokenType::Assign && depth == 0
+    })
+}
+
 pub type LexResult<T> = Result<T, LexerError>;
+
+/// Lexes all of `s`, stopping at the first error.
+pub fn tokenize(s: &str) -> LexResult<Vec<Token>> {
+    Lexer::new(s).collect()
+}
+
+/// Lexes `s` from byte `start`, where line `line` begins, with spans into
+/// all of `s`. A session that keeps every line it was given in one text
+/// can then show an error on whichever line it comes from.
+pub fn tokenize_from(s: &str, start: usize, line: usize) -> LexResult<Vec<Token>> {
+    let lexer = Lexer { pos: start, current_line: line, line_start: start, ..Lexer::new(s) };
+    lexer.collect()
+}
+
 impl Iterator for Lexer {
     type Item = LexResult<Token>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let trivia = match self.skip_trivia() {
+            Ok(t) => t,
+            Err(e) => return Some(Err(e)),
+        };
         if self.pos >= self.text.len() {
-            return None;
+            // comments at the very end still need a token to live on
+            if trivia.is_empty() {
+                return None;
+            }
+            return Some(Ok(Token {
+                span: self.span_from(self.pos),
+                token_type: TokenType::EndExpr,
+                trivia,
+            }));
         }
+        let start = self.pos;
         let slice = &self.text[self.pos..];
         let ch = slice.chars().next().unwrap();
         let ch_len = ch.len_utf8();
@@ -88,70 +379,42 @@ impl Iterator for Lexer {
         };
 
         advance(self, ch_len);
-        match ch {
-            '=' => Some(Ok(Token {
-                line: self.current_line,
-                token_type: TokenType::Assign,
-            })),
-            '+' | '-' | '*' | '/' => Some(Ok(Token {
-                line: self.current_line,
-                token_type: TokenType::Op(ch.to_string()),
-            })),
-            '(' => Some(Ok(Token {
-                line: self.current_line,
-                token_type: TokenType::LParen,
-            })),
-            ')' => Some(Ok(Token {
-                line: self.current_line,
-                token_type: TokenType::RParen,
-            })),
+        let token_type = match ch {
+            '=' | '!' | '<' | '>' if self.text[self.pos..].starts_with('=') => {
+                advance(self, 1);
+                TokenType::Op(format!("{ch}="))
+            }
+            '&' | '|' if self.text[self.pos..].starts_with(ch) => {
+                advance(self, 1);
+                TokenType::Op(format!("{ch}{ch}"))
+            }
+            '!' | '<' | '>' => TokenType::Op(ch.to_string()),
+            '=' => TokenType::Assign,
+            '*' if self.text[self.pos..].starts_with('*') => {
+                advance(self, 1);
+                TokenType::Op("^".to_string())
+            }
+            '+' | '-' | '*' | '/' | '^' | '@' => TokenType::Op(ch.to_string()),
+            '(' => TokenType::LParen,
+            ')' => TokenType::RParen,
+            '[' => TokenType::LBracket,
+            ']' => TokenType::RBracket,
+            ',' => TokenType::Comma,
             '\n' => {
+                let span = self.span_from(start);
                 self.current_line += 1;
-                Some(Ok(Token {
-                    line: self.current_line,
+                self.line_start = self.pos;
+                return Some(Ok(Token {
+                    span,
                     token_type: TokenType::EndExpr,
-                }))
+                    trivia,
+                }));
             }
 
-            d if d.is_ascii_digit() || d == '.' => {
-                let mut seen_dot = d == '.';
-                let mut buf = String::new();
-                buf.push(d);
-
-                while self.pos < self.text.len() {
-                    let next_slice = &self.text[self.pos..];
-                    let next_ch = next_slice.chars().next().unwrap();
-
-                    if next_ch.is_ascii_digit() {
-                        buf.push(next_ch);
-                        advance(self, next_ch.len_utf8());
-                    } else if next_ch == '.' {
-                        if seen_dot {
-                            return Some(Err(LexerError::InvalidNumber(format!(
-                                "Número '{}' contém múltiplos pontos decimais",
-                                buf
-                            ))));
-                        } else {
-                            seen_dot = true;
-                            buf.push('.');
-                            advance(self, next_ch.len_utf8());
-                        }
-                    } else {
-                        break;
-                    }
-                }
-
-                match buf.parse::<f64>() {
-                    Ok(number) => Some(Ok(Token {
-                        line: self.current_line,
-                        token_type: TokenType::Number(number),
-                    })),
-                    Err(e) => Some(Err(LexerError::ParseError(
-                        buf,
-                        format!("Falha ao analisar número: {}", e),
-                    ))),
-                }
-            }
+            d if d.is_ascii_digit() || d == '.' => match self.lex_number(start) {
+                Ok(number) => TokenType::Number(number),
+                Err(e) => return Some(Err(e)),
+            },
             c if c.is_alphabetic() || c == '_' => {
                 let mut buf = String::from(c);
                 while self.pos < self.text.len() {
@@ -164,12 +427,114 @@ impl Iterator for Lexer {
                         break;
                     }
                 }
-                Some(Ok(Token {
-                    line: self.current_line,
-                    token_type: TokenType::Ident(buf),
-                }))
+                match buf.as_str() {
+                    "let" => TokenType::Let,
+                    "if" => TokenType::If,
+                    "then" => TokenType::Then,
+                    "else" => TokenType::Else,
+                    _ => TokenType::Ident(buf),
+                }
             }
-            _ => Some(Err(LexerError::UnrecognizedChar(ch))),
-        }
+            _ => return Some(Err(LexerError::UnrecognizedChar(ch).at(self.span_from(start)))),
+        };
+
+        Some(Ok(Token {
+            span: self.span_from(start),
+            token_type,
+            trivia,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Parser, error::ParsingError};
+
+    fn span(start: usize, end: usize, line: usize, col: usize) -> Span {
+        Span { start, end, line, col }
+    }
+
+    fn types(src: &str) -> Vec<TokenType> {
+        tokenize(src).unwrap().into_iter().map(|t| t.token_type).collect()
+    }
+
+    fn number(value: f64, text: &str) -> TokenType {
+        TokenType::Number(Number { value, text: text.to_string(), imaginary: false })
+    }
+
+    /// The error lexing `src` gives, with its span.
+    fn error(src: &str) -> (LexerError, Span) {
+        let e = tokenize(src).unwrap_err();
+        (e.kind().clone(), e.span().unwrap())
+    }
+
+    fn invalid(msg: &str) -> LexerError {
+        LexerError::InvalidNumber(msg.to_string())
+    }
+
+    #[test]
+    fn decimals_and_exponents() {
+        assert_eq!(types("1_000.5"), [number(1000.5, "1000.5")]);
+        assert_eq!(types("6.022e23"), [number(6.022e23, "6.022e23")]);
+        assert_eq!(types("1E-3 2e+2"), [number(1e-3, "1E-3"), number(200., "2e+2")]);
+        assert_eq!(types(".5"), [number(0.5, ".5")]);
+        assert_eq!(error("1e2.5"), (invalid("the exponent must be a whole number"), span(3, 4, 1, 4)));
+        assert_eq!(error("1ex"), (invalid("expected the exponent's digits, as in 1e-3"), span(2, 3, 1, 3)));
+        assert_eq!(error("1.2.3"), (invalid("a number can only have one decimal point"), span(3, 4, 1, 4)));
+    }
+
+    #[test]
+    fn hex_binary_and_octal_integers() {
+        assert_eq!(types("0xff 0b1010 0o17"), [number(255., "0xff"), number(10., "0b1010"), number(15., "0o17")]);
+        assert_eq!(types("0XFF_FF"), [number(65535., "0XFFFF")]);
+        assert_eq!(error("0b102"), (invalid("`2` is not a base 2 digit"), span(4, 5, 1, 5)));
+        assert_eq!(error("0x"), (invalid("expected base 16 digits after `0x`"), span(2, 2, 1, 3)));
+    }
+
+    #[test]
+    fn imaginary_literals_end_at_the_i() {
+        let three_i = TokenType::Number(Number { value: 3., text: "3".to_string(), imaginary: true });
+        assert_eq!(types("3i"), [three_i]);
+        assert_eq!(types("3in"), [number(3., "3"), TokenType::Ident("in".to_string())]);
+    }
+
+    #[test]
+    fn separators_only_go_between_digits() {
+        let msg = invalid("`_` may only separate two digits, as in 1_000");
+        assert_eq!(error("1__0"), (msg.clone(), span(1, 2, 1, 2)));
+        assert_eq!(error("x = 10_"), (msg.clone(), span(6, 7, 1, 7)));
+        assert_eq!(error("1_.5"), (msg.clone(), span(1, 2, 1, 2)));
+        assert_eq!(error("1._5"), (msg.clone(), span(2, 3, 1, 3)));
+        assert_eq!(error("1e_5"), (msg.clone(), span(2, 3, 1, 3)));
+        assert_eq!(error("a\n0x_ff"), (msg, span(4, 5, 2, 3)));
+        // a leading `_` starts a name
+        assert_eq!(types("_1"), [TokenType::Ident("_1".to_string())]);
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        let tokens = tokenize("1 /* a /* b */ c */ + 2").unwrap();
+        let types: Vec<_> = tokens.iter().map(|t| t.token_type.clone()).collect();
+        assert_eq!(types, [number(1., "1"), TokenType::Op("+".to_string()), number(2., "2")]);
+        assert_eq!(tokens[1].trivia, [Comment { text: "/* a /* b */ c */".to_string(), span: span(2, 19, 1, 3) }]);
+        assert_eq!(tokens[1].span, span(20, 21, 1, 21));
+    }
+
+    #[test]
+    fn lines_are_counted_inside_comments() {
+        let tokens = tokenize("/* a\n/* b */\n*/ x").unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::Ident("x".to_string()));
+        assert_eq!(tokens[0].span, span(16, 17, 3, 4));
+        assert_eq!(error("1 /* /* */"), (LexerError::UnterminatedComment, span(2, 4, 1, 3)));
+    }
+
+    #[test]
+    fn a_space_splits_a_number_into_two() {
+        let tokens = tokenize("1 000").unwrap();
+        assert_eq!(tokens.iter().map(|t| t.span).collect::<Vec<_>>(), [span(0, 1, 1, 1), span(2, 5, 1, 3)]);
+        let e = Parser::new(tokens).parse_expression().unwrap_err();
+        assert_eq!(e.kind(), &ParsingError::MissingOperator("`1`".to_string(), "`000`".to_string()));
+        assert_eq!(e.span(), Some(span(0, 5, 1, 1)));
     }
 }
@@ -0,0 +1,37 @@
+//! Simple Math as a library: parse and evaluate `.math` programs and
+//! expressions in-process through [`Engine`], or compile them with
+//! [`build::build`].
+mod ast;
+mod bigint;
+mod bindings;
+pub mod build;
+mod compile;
+mod complex;
+mod constants;
+mod decimal;
+pub mod declarative;
+mod diagnostic;
+mod diff;
+mod engine;
+mod error;
+mod eval;
+mod lexer;
+mod matrix;
+pub mod optimize;
+mod rational;
+pub mod reactive;
+pub mod solve;
+mod units;
+mod value;
+
+pub use ast::{Assign, ExprKind, Expression, Parser, Program};
+pub use bindings::Definition;
+pub use compile::QBEParser;
+pub use constants::{CONSTANTS, Constant};
+pub use decimal::{Context, Rounding};
+pub use diagnostic::{Diagnose, Diagnostic, use_color};
+pub use engine::{Engine, EngineResult};
+pub use error::{BuildError, CompileError, EngineError, EvalError, LexerError, ParsingError};
+pub use eval::{Binding, EvalResult, Function, HostFunction, Resolver, Warning};
+pub use lexer::{Span, Token, TokenType, is_assignment, tokenize, tokenize_from};
+pub use value::{Mode, Value};
@@ -1,24 +1,25 @@
-use crate::eval::eval_program;
-use crate::repl::REPL;
-use crate::{
-    ast::Parser,
-    lexer::{Lexer, Token},
-    error::*,
+use simple_math::{
+    Context, Diagnose, Diagnostic, Engine, Mode, Parser, Program, QBEParser, Rounding,
+    build::{Emit, build},
+    declarative,
+    optimize::{Options, optimize},
+    solve, tokenize, use_color,
 };
-use std::{env, fs};
-mod ast;
-mod eval;
-mod error;
-mod lexer;
+use std::{env, fs, path::PathBuf, process};
+
+use crate::repl::REPL;
+
 mod repl;
 
 fn main() {
     let args = env::args().skip(1).collect::<Vec<String>>();
+    let color = use_color(args.iter().find_map(|x| x.strip_prefix("--color=")));
+    let mode = eval_mode(&args, color);
 
     match args.first() {
         Some(a) if a == "-i" => {
             println!("<== Welcome to Simple Interactive Mode ==>");
-            let mut repl = REPL::new();
+            let mut repl = REPL::new(color, mode, args.iter().any(|x| x == "--reactive"), solver_options(&args, color));
             loop {
                 if repl.step() {
                     break;
@@ -27,26 +28,57 @@ fn main() {
         }
 
         Some(a) if a == "-f" => {
-            let f_flag_pos = args.iter().position(|x| x == "-f").unwrap();
-            let path = args
-                .get(f_flag_pos + 1)
-                .expect("Please, provide a path after `-f`");
-            let content =
-                fs::read_to_string(path).expect(format!("Error reading file {path}").as_str());
-
-            let tk = tokenize(content.as_str());
-            let mut parser = Parser::new(tk);
-            let program = match parser.parse_program() {
-                Ok(p) => p,
-                Err(e) => panic!("Parser error: {e}"),
-            };
+            let (path, content, program) = read_program(&args, "-f", color);
 
-            let result = match eval_program(program) {
+            let mut engine = Engine::with_mode(mode);
+            engine.set_solver(solver_options(&args, color));
+            let result = match engine.eval_program(program) {
                 Ok(r) => r,
-                Err(e) => panic!("Evaluation Error: {e}"),
+                Err(e) => fail(e.diagnostic(), &path, &content, color),
+            };
+
+            for warning in engine.take_warnings() {
+                eprint!("{}", warning.diagnostic().render(&path, &content, color));
+            }
+            println!("result = {}", mode.show(&result));
+        }
+
+        Some(a) if a == "--simplify" => {
+            let (_, _, program) = read_program(&args, "--simplify", color);
+            let options = Options { mode, assume_finite: args.iter().any(|x| x == "--assume-finite") };
+            print!("{}", optimize(program, options).to_source());
+        }
+
+        Some(a) if a == "-s" => {
+            let (path, content, program) = read_program(&args, "-s", color);
+
+            let mut qbe = QBEParser::new();
+            if let Err(e) = qbe.write_program(optimize(program, compile_options(&args))) {
+                fail(e.diagnostic(), &path, &content, color);
+            }
+
+            print!("{}", qbe.module());
+        }
+
+        Some(a) if a == "-c" || a == "build" => {
+            let (path, content, program) = read_program(&args, a, color);
+            let input = PathBuf::from(&path);
+
+            let emit = match args.iter().find_map(|x| x.strip_prefix("--emit=")) {
+                Some(e) => Emit::parse(e).unwrap_or_else(|| {
+                    let message = format!("Unknown --emit kind '{e}', expected one of: ssa, asm, exe");
+                    fail(Diagnostic::new(message, None), "", "", color)
+                }),
+                None => Emit::Exe,
+            };
+            let output = match args.iter().position(|x| x == "-o") {
+                Some(i) => PathBuf::from(flag_value(&args, i, color)),
+                None => emit.default_output(&input),
             };
 
-            println!("result = {result}");
+            if let Err(e) = build(optimize(program, compile_options(&args)), &output, emit) {
+                fail(e.diagnostic(), &path, &content, color);
+            }
         }
 
         None | Some(_) => usage(),
@@ -58,20 +90,113 @@ fn usage() {
 
 Options:
   -i             Enter interactive mode
+     --reactive         Recompute variables when something they use is redefined
+  --color=auto|always|never  Color error messages (default: auto)
+  --exact        With -i or -f, compute with exact fractions instead of floats
+  --precision <digits>  With -i or -f, compute with decimals of that many significant digits
+     --rounding=half-even|half-up|down  How decimals are rounded (default: half-even)
+  --complex      With -i or -f, compute with complex numbers, `i` being the imaginary unit
+     --polar            Show complex results as magnitude and angle
+  --tolerance <t>       With -i or -f, how close `solve` gets to an answer (default: 1e-12)
+  --max-iterations <n>  With -i or -f, how many steps `solve` may take (default: 100)
   -f <filename>  Evaluate a file and print the result
+  --declarative  With -f, -s or -c, let names be used before the line that defines them
+  --simplify <filename>  Print a file with its constants computed and identities like `x * 1` applied
+     --assume-finite    Also reduce `x * 0`, `x + 0` and `x - 0`, as if every name were a finite number (also for -s and -c)
+  -s <filename>  Print the QBE IL for a file
+  -c <filename>  Compile a file to a native executable (alias: build)
+     -o <path>          Output path (defaults to the file name without extension)
+     --emit=ssa|asm|exe Stop after writing QBE IL, assembly, or link an executable (default)
     ";
     println!("{message}");
 }
 
-// used by file interpreting
-fn tokenize(s: &str) -> Vec<Token> {
-    let lex = Lexer::new(s);
-    let mut vlex = vec![];
-    for t in lex {
-        match t {
-            Err(e) => eprintln!("Tokenizer error: {e}"),
-            Ok(token) => vlex.push(token),
+// prints the diagnostic and exits with a failure status
+fn fail(diagnostic: Diagnostic, file: &str, source: &str, color: bool) -> ! {
+    eprint!("{}", diagnostic.render(file, source, color));
+    process::exit(1);
+}
+
+// the argument after the flag at `pos`
+fn flag_value(args: &[String], pos: usize, color: bool) -> String {
+    match args.get(pos + 1) {
+        Some(v) => v.clone(),
+        None => {
+            let message = format!("Please, provide a path after `{}`", args[pos]);
+            fail(Diagnostic::new(message, None), "", "", color)
         }
     }
-    vlex
+}
+
+// the numbers -i and -f compute with, from --exact, --precision or --complex
+fn eval_mode(args: &[String], color: bool) -> Mode {
+    let rounding = match args.iter().find_map(|x| x.strip_prefix("--rounding=")) {
+        Some(r) => Rounding::parse(r).unwrap_or_else(|| {
+            let message = format!("Unknown --rounding mode '{r}', expected one of: half-even, half-up, down");
+            fail(Diagnostic::new(message, None), "", "", color)
+        }),
+        None => Rounding::default(),
+    };
+    match args.iter().position(|x| x == "--precision") {
+        Some(i) => match flag_value(args, i, color).parse::<u32>() {
+            Ok(digits) if digits > 0 => Mode::Decimal(Context::new(digits, rounding)),
+            _ => fail(Diagnostic::new("--precision takes a positive number of digits", None), "", "", color),
+        },
+        None if args.iter().any(|x| x == "--exact") => Mode::Exact,
+        None if args.iter().any(|x| x == "--complex") => Mode::Complex {
+            polar: args.iter().any(|x| x == "--polar"),
+        },
+        None => Mode::Float,
+    }
+}
+
+// how hard `solve` tries, from --tolerance and --max-iterations
+fn solver_options(args: &[String], color: bool) -> solve::Options {
+    let mut options = solve::Options::default();
+    if let Some(i) = args.iter().position(|x| x == "--tolerance") {
+        options.tolerance = match flag_value(args, i, color).parse::<f64>() {
+            Ok(t) if t > 0. => t,
+            _ => fail(Diagnostic::new("--tolerance takes a positive number", None), "", "", color),
+        };
+    }
+    if let Some(i) = args.iter().position(|x| x == "--max-iterations") {
+        options.max_iterations = match flag_value(args, i, color).parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => fail(Diagnostic::new("--max-iterations takes a positive number of steps", None), "", "", color),
+        };
+    }
+    options
+}
+
+// compiled programs compute with floats
+fn compile_options(args: &[String]) -> Options {
+    Options { mode: Mode::Float, assume_finite: args.iter().any(|x| x == "--assume-finite") }
+}
+
+// reads and parses the file given after `flag`, returning its path and content too; with
+// --declarative its assignments are put in the order they use each other
+fn read_program(args: &[String], flag: &str, color: bool) -> (String, String, Program) {
+    let flag_pos = args.iter().position(|x| x == flag).unwrap();
+    let path = flag_value(args, flag_pos, color);
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) => fail(Diagnostic::new(format!("Error reading file {path} - {e}"), None), "", "", color),
+    };
+
+    let tk = match tokenize(&content) {
+        Ok(tk) => tk,
+        Err(e) => fail(e.diagnostic(), &path, &content, color),
+    };
+    let mut parser = Parser::new(tk);
+    let program = match parser.parse_program() {
+        Ok(p) => p,
+        Err(e) => fail(e.diagnostic(), &path, &content, color),
+    };
+    if !args.iter().any(|x| x == "--declarative") {
+        return (path, content, program);
+    }
+    match declarative::order(program) {
+        Ok(p) => (path, content, p),
+        Err(e) => fail(e.diagnostic(), &path, &content, color),
+    }
 }
@@ -0,0 +1,309 @@
+use crate::{
+    error::EvalError,
+    eval::{EvalResult, divide, multiply, same_dimension},
+    value::{Mode, Value},
+};
+
+/// Rows of values, each as long as the others.
+type Matrix = Vec<Vec<Value>>;
+
+pub type MatrixFn = fn(Vec<Value>, Mode) -> EvalResult<Value>;
+
+/// The functions that take whole vectors and matrices, with their arity.
+pub const BUILTINS: &[(&str, usize, MatrixFn)] = &[
+    ("transpose", 1, transpose),
+    ("det", 1, det),
+    ("inv", 1, inv),
+    ("dot", 2, |a, _| dot(a)),
+    ("cross", 2, |a, _| cross(a)),
+    ("solve", 2, solve),
+];
+
+pub fn builtin(name: &str) -> Option<(usize, MatrixFn)> {
+    BUILTINS.iter().find(|b| b.0 == name).map(|b| (b.1, b.2))
+}
+
+/// A shape in words, for errors: "a number", "a vector of 3" or "a 2x3
+/// matrix".
+pub fn describe(shape: &[usize]) -> String {
+    match shape {
+        [] => "a number".to_string(),
+        [n] => format!("a vector of {n}"),
+        [rows, cols] => format!("a {rows}x{cols} matrix"),
+        dims => format!("an array of {}", dims.iter().map(usize::to_string).collect::<Vec<_>>().join("x")),
+    }
+}
+
+/// `a @ b`: the matrix product, where a vector on the left is a row and
+/// on the right a column. Two vectors give their dot product.
+pub fn matmul(a: Value, b: Value) -> EvalResult<Value> {
+    let (sa, sb) = (a.shape(), b.shape());
+    let fits = matches!(sa.len(), 1 | 2) && matches!(sb.len(), 1 | 2) && sa[sa.len() - 1] == sb[0];
+    if !fits {
+        return Err(EvalError::ShapeMismatch("`@`".to_string(), describe(&sa), describe(&sb)));
+    }
+    let left = match sa.len() {
+        1 => vec![elements(a)],
+        _ => rows(a),
+    };
+    let right = match sb.len() {
+        1 => elements(b).into_iter().map(|x| vec![x]).collect(),
+        _ => rows(b),
+    };
+    let mut product = left
+        .iter()
+        .map(|row| (0..right[0].len()).map(|j| sum_of_products(row, right.iter().map(|r| &r[j]))).collect())
+        .collect::<EvalResult<Matrix>>()?;
+    Ok(match (sa.len(), sb.len()) {
+        (1, 1) => product.remove(0).remove(0),
+        (1, _) => Value::Array(product.remove(0)),
+        (_, 1) => Value::Array(product.into_iter().map(|mut r| r.remove(0)).collect()),
+        _ => from_rows(product),
+    })
+}
+
+fn transpose(args: Vec<Value>, _: Mode) -> EvalResult<Value> {
+    let m = matrix_arg("transpose", args.into_iter().next().unwrap(), false)?;
+    Ok(from_rows((0..m[0].len()).map(|j| m.iter().map(|r| r[j].clone()).collect()).collect()))
+}
+
+/// The determinant, by Gaussian elimination.
+fn det(args: Vec<Value>, mode: Mode) -> EvalResult<Value> {
+    let mut m = matrix_arg("det", args.into_iter().next().unwrap(), true)?;
+    let n = m.len();
+    let mut det: Option<Value> = None;
+    for col in 0..n {
+        let Some(p) = pivot_row(&m, col) else {
+            return Ok(mode.int(0));
+        };
+        if p != col {
+            m.swap(p, col);
+            det = Some(det.map_or(mode.int(-1), |d| -d));
+        }
+        let pivot = m[col][col].clone();
+        for r in col + 1..n {
+            let factor = divide(m[r][col].clone(), pivot.clone())?;
+            m[r] = subtract_scaled(&m[r], &m[col], factor)?;
+        }
+        det = Some(match det {
+            Some(d) => multiply(d, pivot)?,
+            None => pivot,
+        });
+    }
+    Ok(det.expect("matrices aren't empty"))
+}
+
+fn inv(args: Vec<Value>, mode: Mode) -> EvalResult<Value> {
+    let m = matrix_arg("inv", args.into_iter().next().unwrap(), true)?;
+    let n = m.len();
+    let identity = (0..n).map(|i| (0..n).map(|j| mode.int((i == j) as i64)).collect()).collect();
+    Ok(from_rows(eliminate(m, identity)?))
+}
+
+/// `solve(A, b)`: the `x` with `A @ x == b`, for a square `A` and a vector
+/// (or matrix) `b`.
+fn solve(args: Vec<Value>, _: Mode) -> EvalResult<Value> {
+    let mut args = args.into_iter();
+    let a = matrix_arg("solve", args.next().unwrap(), true)?;
+    let b = args.next().unwrap();
+    let shape = b.shape();
+    if !matches!(shape.len(), 1 | 2) || shape[0] != a.len() {
+        let found = describe(&[a.len(), a.len()]);
+        return Err(EvalError::ShapeMismatch("`solve`".to_string(), found, describe(&shape)));
+    }
+    Ok(match shape.len() {
+        1 => {
+            let column = elements(b).into_iter().map(|x| vec![x]).collect();
+            Value::Array(eliminate(a, column)?.into_iter().map(|mut r| r.remove(0)).collect())
+        }
+        _ => from_rows(eliminate(a, rows(b))?),
+    })
+}
+
+fn dot(args: Vec<Value>) -> EvalResult<Value> {
+    let (a, b) = vector_args("dot", args)?;
+    if a.len() != b.len() {
+        return Err(EvalError::ShapeMismatch("`dot`".to_string(), describe(&[a.len()]), describe(&[b.len()])));
+    }
+    sum_of_products(&a, b.iter())
+}
+
+fn cross(args: Vec<Value>) -> EvalResult<Value> {
+    let (a, b) = vector_args("cross", args)?;
+    if a.len() != 3 || b.len() != 3 {
+        let found = if a.len() != 3 { a.len() } else { b.len() };
+        return Err(EvalError::WrongShape("`cross`".to_string(), "vectors of 3".to_string(), describe(&[found])));
+    }
+    let term = |i: usize, j: usize| -> EvalResult<Value> {
+        let (x, y) = (multiply(a[i].clone(), b[j].clone())?, multiply(a[j].clone(), b[i].clone())?);
+        same_dimension(&x.unit(), &y.unit())?;
+        Ok(x - y)
+    };
+    Ok(Value::Array(vec![term(1, 2)?, term(2, 0)?, term(0, 1)?]))
+}
+
+/// Solves `a @ x == b` by Gauss-Jordan elimination on `[a | b]`.
+fn eliminate(a: Matrix, b: Matrix) -> EvalResult<Matrix> {
+    let n = a.len();
+    let mut m: Matrix = a.into_iter().zip(b).map(|(mut row, rhs)| {
+        row.extend(rhs);
+        row
+    }).collect();
+    for col in 0..n {
+        let p = pivot_row(&m, col).ok_or(EvalError::SingularMatrix)?;
+        m.swap(p, col);
+        let pivot = m[col][col].clone();
+        m[col] = m[col].iter().map(|x| divide(x.clone(), pivot.clone())).collect::<EvalResult<_>>()?;
+        for r in (0..n).filter(|&r| r != col) {
+            if !m[r][col].is_zero() {
+                let factor = m[r][col].clone();
+                m[r] = subtract_scaled(&m[r], &m[col], factor)?;
+            }
+        }
+    }
+    Ok(m.into_iter().map(|row| row[n..].to_vec()).collect())
+}
+
+/// The row from `col` down with the largest non-zero entry in column
+/// `col`, which keeps elimination in floating point stable.
+fn pivot_row(m: &Matrix, col: usize) -> Option<usize> {
+    (col..m.len())
+        .filter(|&r| !m[r][col].is_zero())
+        .max_by(|&a, &b| m[a][col].to_complex().abs().total_cmp(&m[b][col].to_complex().abs()))
+}
+
+/// `row - factor * other`.
+fn subtract_scaled(row: &[Value], other: &[Value], factor: Value) -> EvalResult<Vec<Value>> {
+    row.iter()
+        .zip(other)
+        .map(|(x, y)| {
+            let scaled = multiply(factor.clone(), y.clone())?;
+            same_dimension(&x.unit(), &scaled.unit())?;
+            Ok(x.clone() - scaled)
+        })
+        .collect()
+}
+
+fn sum_of_products<'a>(a: &[Value], b: impl Iterator<Item = &'a Value>) -> EvalResult<Value> {
+    let mut products = a.iter().zip(b).map(|(x, y)| multiply(x.clone(), y.clone()));
+    let first = products.next().expect("vectors aren't empty")?;
+    products.try_fold(first, |sum, p| {
+        let p = p?;
+        same_dimension(&sum.unit(), &p.unit())?;
+        Ok(sum + p)
+    })
+}
+
+/// The rows of `v`, when it is a matrix, and square if `square` is set.
+fn matrix_arg(name: &str, v: Value, square: bool) -> EvalResult<Matrix> {
+    let shape = v.shape();
+    match shape[..] {
+        [rows, cols] if !square || rows == cols => Ok(self::rows(v)),
+        _ => {
+            let expected = if square { "a square matrix" } else { "a matrix" };
+            Err(EvalError::WrongShape(format!("`{name}`"), expected.to_string(), describe(&shape)))
+        }
+    }
+}
+
+fn vector_args(name: &str, args: Vec<Value>) -> EvalResult<(Vec<Value>, Vec<Value>)> {
+    if let Some(other) = args.iter().find(|a| a.shape().len() != 1) {
+        return Err(EvalError::WrongShape(format!("`{name}`"), "vectors".to_string(), describe(&other.shape())));
+    }
+    let mut args = args.into_iter().map(elements);
+    Ok((args.next().unwrap(), args.next().unwrap()))
+}
+
+fn elements(v: Value) -> Vec<Value> {
+    match v {
+        Value::Array(elements) => elements,
+        v => vec![v],
+    }
+}
+
+fn rows(v: Value) -> Matrix {
+    elements(v).into_iter().map(elements).collect()
+}
+
+fn from_rows(m: Matrix) -> Value {
+    Value::Array(m.into_iter().map(Value::Array).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rational::Rational;
+
+    /// An exact matrix with integer entries.
+    fn matrix(entries: &[&[i64]]) -> Value {
+        from_rows(entries.iter().map(|row| row.iter().map(|&x| int(x)).collect()).collect())
+    }
+
+    fn vector(entries: &[i64]) -> Value {
+        Value::Array(entries.iter().map(|&x| int(x)).collect())
+    }
+
+    fn int(x: i64) -> Value {
+        Value::Exact(Rational::from_int(x))
+    }
+
+    /// Calls the builtin `name` in exact mode, giving its result printed.
+    fn call(name: &str, args: Vec<Value>) -> EvalResult<String> {
+        let (_, f) = builtin(name).unwrap();
+        f(args, Mode::Exact).map(|v| v.to_string())
+    }
+
+    #[test]
+    fn det_changes_sign_with_each_row_swap() {
+        assert_eq!(call("det", vec![matrix(&[&[1, 2], &[3, 4]])]).unwrap(), "-2");
+        // the pivot search swaps the rows once
+        assert_eq!(call("det", vec![matrix(&[&[0, 1], &[1, 0]])]).unwrap(), "-1");
+        // swapping two rows by hand flips the sign back
+        assert_eq!(call("det", vec![matrix(&[&[3, 4], &[1, 2]])]).unwrap(), "2");
+        // a cycle of three rows is two swaps
+        assert_eq!(call("det", vec![matrix(&[&[0, 0, 2], &[3, 0, 0], &[0, 5, 0]])]).unwrap(), "30");
+        assert_eq!(call("det", vec![matrix(&[&[1, 2], &[2, 4]])]).unwrap(), "0");
+    }
+
+    #[test]
+    fn elimination_inverts_and_solves_exactly() {
+        let a = matrix(&[&[2, 1], &[1, 3]]);
+        assert_eq!(call("inv", vec![a.clone()]).unwrap(), "[[3/5, -1/5], [-1/5, 2/5]]");
+        assert_eq!(call("solve", vec![a.clone(), vector(&[3, 5])]).unwrap(), "[4/5, 7/5]");
+        assert_eq!(call("solve", vec![a, matrix(&[&[3, 0], &[5, 1]])]).unwrap(), "[[4/5, -1/5], [7/5, 2/5]]");
+        // a zero on the diagonal needs a row swap
+        assert_eq!(call("inv", vec![matrix(&[&[0, 1], &[1, 0]])]).unwrap(), "[[0, 1], [1, 0]]");
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        let singular = matrix(&[&[1, 2], &[2, 4]]);
+        assert_eq!(call("inv", vec![singular.clone()]).unwrap_err(), EvalError::SingularMatrix);
+        assert_eq!(call("solve", vec![singular, vector(&[1, 2])]).unwrap_err(), EvalError::SingularMatrix);
+    }
+
+    #[test]
+    fn shapes_are_checked() {
+        let wrong = |name: &str, expected: &str, found: &str| {
+            EvalError::WrongShape(format!("`{name}`"), expected.to_string(), found.to_string())
+        };
+        let mismatch = |op: &str, a: &str, b: &str| EvalError::ShapeMismatch(format!("`{op}`"), a.to_string(), b.to_string());
+        let wide = matrix(&[&[1, 2, 3], &[4, 5, 6]]);
+        assert_eq!(call("det", vec![wide.clone()]).unwrap_err(), wrong("det", "a square matrix", "a 2x3 matrix"));
+        assert_eq!(call("transpose", vec![vector(&[1, 2])]).unwrap_err(), wrong("transpose", "a matrix", "a vector of 2"));
+        assert_eq!(call("cross", vec![vector(&[1, 2]), vector(&[1, 2])]).unwrap_err(), wrong("cross", "vectors of 3", "a vector of 2"));
+        assert_eq!(call("dot", vec![vector(&[1, 2]), vector(&[1, 2, 3])]).unwrap_err(), mismatch("dot", "a vector of 2", "a vector of 3"));
+        let square = matrix(&[&[1, 0], &[0, 1]]);
+        assert_eq!(call("solve", vec![square, vector(&[1, 2, 3])]).unwrap_err(), mismatch("solve", "a 2x2 matrix", "a vector of 3"));
+        assert_eq!(matmul(wide.clone(), wide).unwrap_err(), mismatch("@", "a 2x3 matrix", "a 2x3 matrix"));
+    }
+
+    #[test]
+    fn matmul_treats_vectors_as_rows_or_columns() {
+        let m = matrix(&[&[1, 2], &[3, 4]]);
+        assert_eq!(matmul(vector(&[1, 1]), m.clone()).unwrap().to_string(), "[4, 6]");
+        assert_eq!(matmul(m.clone(), vector(&[1, 1])).unwrap().to_string(), "[3, 7]");
+        assert_eq!(matmul(vector(&[1, 2]), vector(&[3, 4])).unwrap().to_string(), "11");
+        assert_eq!(call("transpose", vec![m]).unwrap(), "[[1, 3], [2, 4]]");
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{
+    ast::{Assign, ExprKind, Expression, Program},
+    eval::{self, Globals},
+    lexer::{Number, Span, TokenType, tokenize},
+    rational::Rational,
+    value::{Mode, Value},
+};
+
+/// How `optimize` may rewrite a program.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// The mode the program will run in, so that folded constants give
+    /// the same values: `1 / 4` folds to `0.25` in any mode, but `1 / 3`
+    /// stays as it is in exact mode.
+    pub mode: Mode,
+    /// Lets `x * 0` become 0 and `x + 0` and `x - 0` become `x`, which
+    /// only holds when `x` is a finite number without units, not a vector
+    /// and not -0. Otherwise they are left for the program to compute.
+    pub assume_finite: bool,
+}
+
+/// `program` with constant parts computed, parentheses dropped (printing
+/// puts back those that are needed) and identities like `x * 1` applied.
+/// `0 / x` is left as a division, since it fails when `x` is 0, unless
+/// `x` is a constant it can be computed with, and `0 - x` is left as a
+/// subtraction, as `-x` differs from it when `x` is 0.
+pub fn optimize(program: Program, options: Options) -> Program {
+    let functions = program.body.iter().filter(|a| !a.params.is_empty()).map(|a| a.name.clone()).collect::<Vec<_>>();
+    let optimizer = Optimizer { options, functions };
+    let body = program.body.into_iter().map(|a| Assign { expr: optimizer.expression(a.expr), ..a }).collect();
+    Program { body }
+}
+
+/// `e` on its own optimized the way `optimize` does an assignment.
+pub fn simplify(e: Expression, options: Options) -> Expression {
+    Optimizer { options, functions: Vec::new() }.expression(e)
+}
+
+struct Optimizer {
+    options: Options,
+    /// Names the program defines as functions, which calls can't be folded
+    /// for even when they share a builtin's name.
+    functions: Vec<String>,
+}
+
+impl Optimizer {
+    fn expression(&self, e: Expression) -> Expression {
+        let span = e.span;
+        let kind = match e.kind {
+            ExprKind::Parenthed(inner) => return self.expression(*inner),
+            ExprKind::Convert(inner, unit) => ExprKind::Convert(Box::new(self.expression(*inner)), unit),
+            ExprKind::Array(elements) => ExprKind::Array(self.all(elements)),
+            ExprKind::Call(name, args) => {
+                let args = self.all(args);
+                let folds = eval::builtin(&name).is_some() && !self.functions.contains(&name);
+                let call = Expression::new(ExprKind::Call(name, args), span);
+                return match folds {
+                    true => self.fold(call),
+                    false => call,
+                };
+            }
+            ExprKind::If(cond, then, otherwise) => {
+                let cond = self.expression(*cond);
+                match self.constant(&cond) {
+                    Some(c) if c.truthy() => return self.expression(*then),
+                    Some(_) => return self.expression(*otherwise),
+                    None => {
+                        let (then, otherwise) = (self.expression(*then), self.expression(*otherwise));
+                        ExprKind::If(Box::new(cond), Box::new(then), Box::new(otherwise))
+                    }
+                }
+            }
+            ExprKind::Operation(op, operands) => return self.operation(op, self.all(operands), span),
+            kind => kind,
+        };
+        Expression::new(kind, span)
+    }
+
+    fn all(&self, exprs: Vec<Expression>) -> Vec<Expression> {
+        exprs.into_iter().map(|e| self.expression(e)).collect()
+    }
+
+    /// An operation on already optimized operands.
+    fn operation(&self, op: String, mut operands: Vec<Expression>, span: Span) -> Expression {
+        if operands.len() == 1 {
+            let operand = operands.pop().unwrap();
+            return match (op.as_str(), operand.kind) {
+                ("+", kind) => Expression::new(kind, operand.span),
+                // `- -x` is `x`, and `-+x` already lost its `+`
+                ("-", ExprKind::Operation(inner, mut x)) if inner == "-" && x.len() == 1 => x.pop().unwrap(),
+                (_, kind) => {
+                    let operand = Expression::new(kind, operand.span);
+                    self.fold(Expression::new(ExprKind::Operation(op, vec![operand]), span))
+                }
+            };
+        }
+
+        let rhs = operands.pop().unwrap();
+        let lhs = operands.pop().unwrap();
+        let one = |e: &Expression| is_number(e, 1);
+        // literals on both sides are left to `fold`
+        let zero = |e: &Expression| self.options.assume_finite && is_number(e, 0);
+        match op.as_str() {
+            "*" if one(&rhs) => return lhs,
+            "*" if one(&lhs) => return rhs,
+            "/" | "^" if one(&rhs) => return lhs,
+            "+" | "-" if zero(&rhs) => return lhs,
+            "+" if zero(&lhs) => return rhs,
+            "*" if zero(&rhs) => return rhs,
+            "*" if zero(&lhs) => return lhs,
+            _ => {}
+        }
+
+        // `a + -b` is `a - b`, and `a - -b` is `a + b`
+        if let ExprKind::Operation(inner, negated) = &rhs.kind
+            && (op == "+" || op == "-")
+            && inner == "-"
+            && negated.len() == 1
+        {
+            let flipped = if op == "+" { "-" } else { "+" };
+            let ExprKind::Operation(_, mut negated) = rhs.kind else { unreachable!() };
+            return self.operation(flipped.to_string(), vec![lhs, negated.pop().unwrap()], span);
+        }
+
+        self.fold(Expression::new(ExprKind::Operation(op, vec![lhs, rhs]), span))
+    }
+
+    /// `e` computed into a literal, when its operands are literals and the
+    /// result can be written back exactly.
+    fn fold(&self, e: Expression) -> Expression {
+        let operands = match &e.kind {
+            ExprKind::Operation(_, operands) | ExprKind::Call(_, operands) => operands,
+            _ => return e,
+        };
+        if !operands.iter().all(|o| self.constant(o).is_some()) {
+            return e;
+        }
+        let Some(value) = self.evaluate(&e) else {
+            return e;
+        };
+        let negative = value.to_f64() < 0.;
+        let magnitude = if negative { -value.clone() } else { value.clone() };
+        match self.literal(&magnitude) {
+            Some(n) if !negative => Expression::new(ExprKind::Num(n), e.span),
+            Some(n) => {
+                let n = Expression::new(ExprKind::Num(n), e.span);
+                Expression::new(ExprKind::Operation("-".to_string(), vec![n]), e.span)
+            }
+            None => e,
+        }
+    }
+
+    /// The value of a literal, or of a negated one.
+    fn constant(&self, e: &Expression) -> Option<Value> {
+        match &e.kind {
+            ExprKind::Num(_) => self.evaluate(e),
+            ExprKind::Operation(op, operands) if op == "-" && operands.len() == 1 => match operands[0].kind {
+                ExprKind::Num(_) => self.evaluate(e),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Evaluates `e` in the program's mode, unless it fails or raises a
+    /// warning, which it should still do when the program runs.
+    fn evaluate(&self, e: &Expression) -> Option<Value> {
+        let globals = Globals::with_mode(self.options.mode);
+        let value = eval::eval_expr(e.clone(), &globals.env()).ok()?;
+        globals.take_warnings().is_empty().then_some(value)
+    }
+
+    /// A number literal that reads back as exactly `v` in the program's
+    /// mode, if there is one.
+    fn literal(&self, v: &Value) -> Option<Number> {
+        let text = match v {
+            Value::Float(f) if f.is_finite() => match format!("{f}") {
+                short if short.len() <= 24 => short,
+                _ => format!("{f:e}"),
+            },
+            Value::Exact(r) if r.is_integer() => r.to_string(),
+            Value::Exact(r) => match r.to_decimal(40) {
+                (decimal, true) => decimal,
+                _ => return None,
+            },
+            Value::Decimal(d, _) => d.to_string(),
+            _ => return None,
+        };
+        let n = match tokenize(&text).ok()?.as_slice() {
+            [token] => match &token.token_type {
+                TokenType::Number(n) if !n.imaginary => n.clone(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let mode = self.options.mode;
+        (mode.show(&mode.literal(&n)) == mode.show(v)).then_some(n)
+    }
+}
+
+/// Whether `e` is a literal for exactly `value`, so `1.0` but not
+/// `1.00000000000000000001`, which is 1 only as a float.
+fn is_number(e: &Expression, value: i64) -> bool {
+    matches!(&e.kind, ExprKind::Num(n) if !n.imaginary && Rational::parse(&n.text) == Some(Rational::from_int(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Parser;
+
+    fn optimized(source: &str, options: Options) -> String {
+        let program = Parser::new(tokenize(source).unwrap()).parse_program().unwrap();
+        optimize(program, options).to_source()
+    }
+
+    fn simplified(source: &str) -> String {
+        optimized(source, Options::default())
+    }
+
+    fn assuming_finite(source: &str) -> String {
+        optimized(source, Options { assume_finite: true, ..Options::default() })
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(simplified("a = 2 * 3 + 1 # seven"), "a = 7 # seven\n");
+        assert_eq!(simplified("a = x * (2 + 3)"), "a = x*5\n");
+        assert_eq!(simplified("a = sqrt(16) - 5"), "a = -1\n");
+        assert_eq!(simplified("a = if 1 < 2 then x else y"), "a = x\n");
+        assert_eq!(simplified("a = 2 * 0"), "a = 0\n");
+    }
+
+    #[test]
+    fn folds_only_what_reads_back_exactly() {
+        assert_eq!(simplified("a = 1 / 4"), "a = 0.25\n");
+        let exact = Options { mode: Mode::Exact, ..Options::default() };
+        assert_eq!(optimized("a = 1 / 3", exact), "a = 1/3\n");
+        assert_eq!(optimized("a = 1 / 8", exact), "a = 0.125\n");
+        // a failing or warning constant is left to fail when the program runs
+        assert_eq!(simplified("a = 1 / 0"), "a = 1/0\n");
+    }
+
+    #[test]
+    fn a_function_named_like_a_builtin_is_not_folded() {
+        assert_eq!(simplified("sqrt(x) = x\na = sqrt(16)"), "sqrt(x) = x\na = sqrt(16)\n");
+    }
+
+    #[test]
+    fn applies_the_identities_that_always_hold() {
+        assert_eq!(simplified("a = (x * 1) / 1 ^ 1"), "a = x\n");
+        assert_eq!(simplified("a = 1 * x ^ 1"), "a = x\n");
+        assert_eq!(simplified("a = - -x + -y"), "a = x - y\n");
+        assert_eq!(simplified("a = (k * 1) - -(-(k)) * sqrt(16)"), "a = k - k*4\n");
+    }
+
+    #[test]
+    fn keeps_zero_identities_that_depend_on_the_operand() {
+        // NaN for an infinite `x`, a vector for a vector, a unit error for `3 m`
+        assert_eq!(simplified("a = x * 0"), "a = x*0\n");
+        assert_eq!(simplified("a = 0 * v"), "a = 0*v\n");
+        assert_eq!(simplified("a = 3 m + 0"), "a = 3 m + 0\n");
+        assert_eq!(simplified("a = 0 + x - 0"), "a = 0 + x - 0\n");
+    }
+
+    #[test]
+    fn assuming_finite_applies_zero_identities() {
+        assert_eq!(assuming_finite("a = x * 0 + y"), "a = y\n");
+        assert_eq!(assuming_finite("a = 0 * x"), "a = 0\n");
+        assert_eq!(assuming_finite("a = 0 + x - 0"), "a = x\n");
+    }
+
+    #[test]
+    fn zero_minus_x_stays_a_subtraction() {
+        // `-x` is -0 when `x` is 0, where `0 - x` is 0
+        assert_eq!(simplified("a = 0 - x"), "a = 0 - x\n");
+        assert_eq!(assuming_finite("a = 0 - x"), "a = 0 - x\n");
+    }
+
+    #[test]
+    fn zero_over_x_stays_a_division() {
+        assert_eq!(assuming_finite("a = 0 / x"), "a = 0/x\n");
+        assert_eq!(simplified("a = 0 / 0"), "a = 0/0\n");
+        assert_eq!(simplified("a = 0 / 4"), "a = 0\n");
+    }
+}
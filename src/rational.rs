@@ -0,0 +1,326 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+use crate::bigint::{BigInt, ldexp};
+
+/// An exact fraction, always in lowest terms with a positive denominator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rational {
+    num: BigInt,
+    den: BigInt,
+}
+
+/// Exponents beyond this in literals or `^` would build integers too
+/// large to be useful, so those fall back to floating point.
+pub const MAX_EXACT_EXPONENT: i64 = 100_000;
+
+/// Powers whose numerator or denominator may have more bits than this
+/// fall back to floating point too. It lets `10^MAX_EXACT_EXPONENT` stay
+/// exact, but not `(10^1000)^1000`.
+pub const MAX_EXACT_BITS: u64 = 4 * MAX_EXACT_EXPONENT as u64;
+
+impl Rational {
+    /// `num / den`, reduced. Panics when `den` is zero.
+    pub fn new(num: BigInt, den: BigInt) -> Self {
+        assert!(!den.is_zero(), "Rational with a zero denominator");
+        let g = num.gcd(&den);
+        let (mut num, mut den) = (&num / &g, &den / &g);
+        if den.is_negative() {
+            num = -num;
+            den = -den;
+        }
+        Self { num, den }
+    }
+
+    pub fn from_int(n: impl Into<BigInt>) -> Self {
+        Self {
+            num: n.into(),
+            den: BigInt::one(),
+        }
+    }
+
+    /// Parses a number literal as written, without `_` separators:
+    /// `42`, `0.1`, `6.022e23`, `0xff`, `0b101`, `0o17`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let radix = match text.get(..2) {
+            Some("0x" | "0X") => 16,
+            Some("0b" | "0B") => 2,
+            Some("0o" | "0O") => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            return BigInt::from_digits(&text[2..], radix).map(Self::from_int);
+        }
+
+        let (mantissa, exp) = match text.find(['e', 'E']) {
+            Some(i) => (&text[..i], text[i + 1..].parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits = BigInt::from_digits(&format!("{int}{frac}"), 10)?;
+        let scale = exp - frac.len() as i64;
+        if scale.abs() > MAX_EXACT_EXPONENT {
+            return None;
+        }
+        let power = BigInt::from(10u64).pow(scale.unsigned_abs() as u32);
+        match scale >= 0 {
+            true => Some(Self::from_int(&digits * &power)),
+            false => Some(Self::new(digits, power)),
+        }
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.num
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.den
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.num.is_zero()
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.den.is_one()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.num.is_negative()
+    }
+
+    pub fn abs(&self) -> Self {
+        Self {
+            num: self.num.abs(),
+            den: self.den.clone(),
+        }
+    }
+
+    pub fn floor(&self) -> Self {
+        Self::from_int(self.num.div_floor(&self.den))
+    }
+
+    pub fn ceil(&self) -> Self {
+        -(-self.clone()).floor()
+    }
+
+    /// Rounds half away from zero, like `f64::round`.
+    pub fn round(&self) -> Self {
+        let half = Self::new(BigInt::one(), BigInt::from(2u64));
+        match self.is_negative() {
+            true => -(self.abs() + half).floor(),
+            false => (self.clone() + half).floor(),
+        }
+    }
+
+    /// At least as many bits as the numerator and denominator of
+    /// `self ^ exp` have, found without computing it.
+    pub fn pow_bits(&self, exp: i64) -> u64 {
+        self.num.bits().max(self.den.bits()).saturating_mul(exp.unsigned_abs())
+    }
+
+    /// `self ^ exp`, or `None` for zero to a negative power.
+    pub fn pow(&self, exp: i64) -> Option<Self> {
+        let e = exp.unsigned_abs() as u32;
+        let p = Self {
+            num: self.num.pow(e),
+            den: self.den.pow(e),
+        };
+        match exp < 0 {
+            true if p.is_zero() => None,
+            true => Some(Self::new(p.den, p.num)),
+            false => Some(p),
+        }
+    }
+
+    /// The exact square root, when both numerator and denominator are
+    /// perfect squares.
+    pub fn sqrt(&self) -> Option<Self> {
+        let num = self.num.isqrt()?;
+        let den = self.den.isqrt()?;
+        (&num * &num == self.num && &den * &den == self.den).then_some(Self { num, den })
+    }
+
+    /// The nearest `f64`, give or take the last bit.
+    pub fn to_f64(&self) -> f64 {
+        let (num, den) = (self.num.abs(), &self.den);
+        // scale so the integer quotient keeps 64 significant bits
+        let shift = 64 + den.bits() as i64 - num.bits() as i64;
+        let q = match shift >= 0 {
+            true => &num.shl(shift as u64) / den,
+            false => &num / &den.shl((-shift) as u64),
+        };
+        let magnitude = ldexp(q.to_f64(), -shift);
+        if self.is_negative() { -magnitude } else { magnitude }
+    }
+
+    /// Decimal expansion rounded to at most `digits` decimal places, and
+    /// whether it is exact.
+    pub fn to_decimal(&self, digits: usize) -> (String, bool) {
+        let scale = BigInt::from(10u64).pow(digits as u32);
+        let (mut q, r) = (&self.num.abs() * &scale).div_rem(&self.den);
+        let exact = r.is_zero();
+        if &r + &r >= self.den {
+            q = &q + &BigInt::one();
+        }
+
+        let mut text = q.to_string();
+        if text.len() <= digits {
+            text.insert_str(0, &"0".repeat(digits + 1 - text.len()));
+        }
+        text.insert(text.len() - digits, '.');
+        if exact {
+            text = text.trim_end_matches('0').trim_end_matches('.').to_string();
+        }
+        if self.is_negative() {
+            text.insert(0, '-');
+        }
+        (text, exact)
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.num * &other.den).cmp(&(&other.num * &self.den))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.is_integer() {
+            true => write!(f, "{}", self.num),
+            false => write!(f, "{}/{}", self.num, self.den),
+        }
+    }
+}
+
+impl Neg for Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Self {
+            num: -self.num,
+            den: self.den,
+        }
+    }
+}
+
+impl Add for Rational {
+    type Output = Rational;
+
+    fn add(self, other: Rational) -> Rational {
+        Self::new(&(&self.num * &other.den) + &(&other.num * &self.den), &self.den * &other.den)
+    }
+}
+
+impl Sub for Rational {
+    type Output = Rational;
+
+    fn sub(self, other: Rational) -> Rational {
+        self + -other
+    }
+}
+
+impl Mul for Rational {
+    type Output = Rational;
+
+    fn mul(self, other: Rational) -> Rational {
+        Self::new(&self.num * &other.num, &self.den * &other.den)
+    }
+}
+
+impl Div for Rational {
+    type Output = Rational;
+
+    /// Panics when `other` is zero; callers check for that first.
+    fn div(self, other: Rational) -> Rational {
+        Self::new(&self.num * &other.den, &self.den * &other.num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(num: i64, den: i64) -> Rational {
+        Rational::new(BigInt::from(num), BigInt::from(den))
+    }
+
+    #[test]
+    fn new_reduces_and_keeps_the_denominator_positive() {
+        let r = ratio(6, -8);
+        assert_eq!((r.numer().to_string(), r.denom().to_string()), ("-3".to_string(), "4".to_string()));
+        assert_eq!(ratio(0, -5), Rational::from_int(0i64));
+        assert_eq!(ratio(-4, -2), Rational::from_int(2i64));
+    }
+
+    #[test]
+    fn parses_decimal_literals_exactly() {
+        assert_eq!(Rational::parse("1e-5"), Some(ratio(1, 100_000)));
+        assert_eq!(Rational::parse(".5"), Some(ratio(1, 2)));
+        assert_eq!(Rational::parse("0.1"), Some(ratio(1, 10)));
+        assert_eq!(Rational::parse("2.5E3"), Some(Rational::from_int(2500i64)));
+        assert_eq!(Rational::parse("0x1f"), Some(Rational::from_int(31i64)));
+        assert_eq!(Rational::parse("1.2.3"), None);
+    }
+
+    #[test]
+    fn parse_refuses_huge_exponents() {
+        assert!(Rational::parse("1e1000").is_some());
+        assert_eq!(Rational::parse("1e100001"), None);
+        assert_eq!(Rational::parse("1e-200000"), None);
+        assert_eq!(Rational::parse("1e99999999999999999999"), None);
+    }
+
+    #[test]
+    fn pow_bits_bounds_the_size_of_a_power() {
+        for (r, exp) in [(ratio(10, 1), 7), (ratio(-3, 7), 5), (ratio(7, 3), -4), (ratio(1, 1), 1000)] {
+            let p = r.pow(exp).unwrap();
+            assert!(p.numer().bits().max(p.denom().bits()) <= r.pow_bits(exp));
+        }
+        let googol = Rational::parse("1e100").unwrap();
+        assert!(googol.pow_bits(100_000) > MAX_EXACT_BITS);
+        assert!(ratio(10, 1).pow_bits(MAX_EXACT_EXPONENT) <= MAX_EXACT_BITS);
+        assert_eq!(ratio(2, 1).pow_bits(i64::MIN), u64::MAX);
+    }
+
+    #[test]
+    fn to_f64_rounds_to_the_nearest_float() {
+        assert_eq!(ratio(1, 3).to_f64(), 1. / 3.);
+        assert_eq!(ratio(-2, 3).to_f64(), -2. / 3.);
+        assert_eq!(Rational::parse("0.1").unwrap().to_f64(), 0.1);
+        assert_eq!(Rational::parse("1e-300").unwrap().to_f64(), 1e-300);
+        assert_eq!(Rational::parse("123456789012345678901234567890").unwrap().to_f64(), 1.2345678901234568e29);
+    }
+
+    #[test]
+    fn rounding_goes_half_away_from_zero() {
+        assert_eq!(ratio(5, 2).round(), Rational::from_int(3i64));
+        assert_eq!(ratio(-5, 2).round(), Rational::from_int(-3i64));
+        assert_eq!(ratio(-7, 2).floor(), Rational::from_int(-4i64));
+        assert_eq!(ratio(-7, 2).ceil(), Rational::from_int(-3i64));
+    }
+
+    #[test]
+    fn pow_and_sqrt_stay_exact() {
+        assert_eq!(ratio(2, 3).pow(-2), Some(ratio(9, 4)));
+        assert_eq!(Rational::from_int(0i64).pow(-1), None);
+        assert_eq!(ratio(9, 4).sqrt(), Some(ratio(3, 2)));
+        assert_eq!(ratio(2, 1).sqrt(), None);
+    }
+
+    #[test]
+    fn to_decimal_says_whether_it_is_exact() {
+        assert_eq!(ratio(1, 8).to_decimal(10), ("0.125".to_string(), true));
+        assert_eq!(ratio(-2, 3).to_decimal(4), ("-0.6667".to_string(), false));
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    ast::Assign,
+    engine::{Engine, EngineResult},
+    error::EvalError,
+    eval::Binding,
+    value::Value,
+};
+
+/// An assignment kept to be run again when a name it uses is redefined.
+#[derive(Debug, Clone)]
+struct Formula {
+    assign: Assign,
+    /// The line it was typed on, to point errors at.
+    source: String,
+    uses: Vec<String>,
+}
+
+/// A variable run again because something it uses was redefined.
+#[derive(Debug)]
+pub struct Update {
+    pub name: String,
+    pub before: Option<Value>,
+    pub after: EngineResult<Value>,
+    /// The line the variable was defined on.
+    pub source: String,
+}
+
+/// Assignments that stay live, like the cells of a spreadsheet: after
+/// `area = pi * r ^ 2`, redefining `r` recomputes `area`.
+#[derive(Debug, Default)]
+pub struct Sheet {
+    formulas: Vec<Formula>,
+}
+
+impl Sheet {
+    /// Defines `assign`, typed as `source`, in `engine`, then recomputes
+    /// every variable that uses it, directly or not, each after those it
+    /// uses. An assignment that would make a variable depend on itself is
+    /// refused.
+    pub fn define(&mut self, assign: Assign, source: &str, engine: &mut Engine) -> EngineResult<Vec<Update>> {
+        let formula = Formula { uses: assign.dependencies(), source: source.to_string(), assign };
+        let name = formula.assign.name.clone();
+        let previous = self.formulas.iter().position(|f| f.assign.name == name).map(|i| self.formulas.remove(i));
+        self.formulas.push(formula);
+        let assign = &self.formulas.last().unwrap().assign;
+        let checked = match self.cycle(&name) {
+            Some(path) => Err(EvalError::Cycle(path.into_iter().map(|n| (n, None)).collect()).at(assign.span).into()),
+            None => engine.define(assign.clone()).map(|_| ()),
+        };
+        if let Err(e) = checked {
+            self.formulas.pop();
+            self.formulas.extend(previous);
+            return Err(e);
+        }
+
+        let mut updates = Vec::new();
+        for f in self.dependents(&name) {
+            if !f.assign.params.is_empty() {
+                continue;
+            }
+            let before = engine.get_var(&f.assign.name);
+            let after = engine.define(f.assign.clone()).map(|b| match b {
+                Binding::Value(v) => v.clone(),
+                _ => unreachable!("formulas without parameters define variables"),
+            });
+            updates.push(Update { name: f.assign.name.clone(), before, after, source: f.source.clone() });
+        }
+        Ok(updates)
+    }
+
+    /// Stops recomputing `name`, which now holds a plain value.
+    pub fn forget(&mut self, name: &str) {
+        self.formulas.retain(|f| f.assign.name != name);
+    }
+
+    fn formula(&self, name: &str) -> Option<&Formula> {
+        self.formulas.iter().find(|f| f.assign.name == name)
+    }
+
+    /// The formulas that use `name` directly or not, each after the ones
+    /// it uses.
+    fn dependents(&self, name: &str) -> Vec<&Formula> {
+        let mut affected = HashSet::from([name]);
+        loop {
+            let before = affected.len();
+            for f in &self.formulas {
+                if f.uses.iter().any(|u| affected.contains(u.as_str())) {
+                    affected.insert(&f.assign.name);
+                }
+            }
+            if affected.len() == before {
+                break;
+            }
+        }
+        affected.remove(name);
+
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        for f in &self.formulas {
+            self.visit(f, &affected, &mut visited, &mut order);
+        }
+        order
+    }
+
+    fn visit<'a>(
+        &'a self,
+        f: &'a Formula,
+        affected: &HashSet<&str>,
+        visited: &mut HashSet<&'a str>,
+        order: &mut Vec<&'a Formula>,
+    ) {
+        if !affected.contains(f.assign.name.as_str()) || !visited.insert(&f.assign.name) {
+            return;
+        }
+        for used in f.uses.iter().filter_map(|u| self.formula(u)) {
+            self.visit(used, affected, visited, order);
+        }
+        order.push(f);
+    }
+
+    /// A chain of names from `name` back to itself that goes through a
+    /// variable. Functions may call themselves, but a variable can't be
+    /// computed from its own value.
+    fn cycle(&self, name: &str) -> Option<Vec<String>> {
+        if self.formula(name)?.assign.params.is_empty() {
+            return self.path(name, name);
+        }
+        self.formulas.iter().filter(|f| f.assign.params.is_empty()).find_map(|v| {
+            let mut path = self.path(name, &v.assign.name)?;
+            path.extend(self.path(&v.assign.name, name)?.into_iter().skip(1));
+            Some(path)
+        })
+    }
+
+    /// The shortest chain of uses from `from` to `to`, both included.
+    fn path(&self, from: &str, to: &str) -> Option<Vec<String>> {
+        let mut previous: HashMap<&str, &str> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(name) = queue.pop_front() {
+            for used in self.formula(name).into_iter().flat_map(|f| &f.uses) {
+                if used == to {
+                    let mut path = vec![to.to_string(), name.to_string()];
+                    let mut at = name;
+                    while at != from {
+                        at = previous[at];
+                        path.push(at.to_string());
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                if used != from && !previous.contains_key(used.as_str()) {
+                    previous.insert(used, name);
+                    queue.push_back(used);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Parser, error::EngineError, lexer::tokenize};
+
+    fn float(v: &Value) -> f64 {
+        match v {
+            Value::Float(x) => *x,
+            v => panic!("not a float: {v:?}"),
+        }
+    }
+
+    /// Defines `source` and lists the variables recomputed, with their
+    /// values before and after.
+    fn define(sheet: &mut Sheet, engine: &mut Engine, source: &str) -> EngineResult<Vec<(String, Option<f64>, f64)>> {
+        let assign = Parser::new(tokenize(source).unwrap()).parse_assign().unwrap();
+        let updates = sheet.define(assign, source, engine)?;
+        Ok(updates.into_iter().map(|u| (u.name, u.before.as_ref().map(float), float(&u.after.unwrap()))).collect())
+    }
+
+    fn cycle(names: &[&str]) -> EvalError {
+        EvalError::Cycle(names.iter().map(|n| (n.to_string(), None)).collect())
+    }
+
+    fn kind(e: EngineError) -> EvalError {
+        match e {
+            EngineError::Eval(e) => e.kind().clone(),
+            e => panic!("not an eval error: {e:?}"),
+        }
+    }
+
+    #[test]
+    fn redefining_a_name_recomputes_its_users_in_order() {
+        let (mut sheet, mut engine) = (Sheet::default(), Engine::new());
+        for source in ["d = 2", "r = d / 2", "area = 3 * r ^ 2", "double = 2 * area + r"] {
+            assert_eq!(define(&mut sheet, &mut engine, source).unwrap(), []);
+        }
+        let updates = define(&mut sheet, &mut engine, "d = 4").unwrap();
+        let expected = [
+            ("r".to_string(), Some(1.), 2.),
+            ("area".to_string(), Some(3.), 12.),
+            ("double".to_string(), Some(7.), 26.),
+        ];
+        assert_eq!(updates, expected);
+        assert_eq!(engine.get_var("double").map(|v| float(&v)), Some(26.));
+    }
+
+    #[test]
+    fn only_the_users_of_a_name_are_recomputed() {
+        let (mut sheet, mut engine) = (Sheet::default(), Engine::new());
+        for source in ["a = 1", "b = 2", "c = a + 1", "d = b + 1"] {
+            define(&mut sheet, &mut engine, source).unwrap();
+        }
+        assert_eq!(define(&mut sheet, &mut engine, "b = 5").unwrap(), [("d".to_string(), Some(3.), 6.)]);
+    }
+
+    #[test]
+    fn a_function_recomputes_the_variables_calling_it() {
+        let (mut sheet, mut engine) = (Sheet::default(), Engine::new());
+        for source in ["f(x) = x + 1", "y = f(2)"] {
+            define(&mut sheet, &mut engine, source).unwrap();
+        }
+        assert_eq!(define(&mut sheet, &mut engine, "f(x) = x * 10").unwrap(), [("y".to_string(), Some(3.), 20.)]);
+    }
+
+    #[test]
+    fn a_cycle_is_refused_and_the_old_definition_kept() {
+        let (mut sheet, mut engine) = (Sheet::default(), Engine::new());
+        for source in ["a = 1", "b = a + 1", "c = b * 2"] {
+            define(&mut sheet, &mut engine, source).unwrap();
+        }
+        let e = define(&mut sheet, &mut engine, "a = c - 1").unwrap_err();
+        assert_eq!(kind(e), cycle(&["a", "c", "b", "a"]));
+        assert_eq!(kind(define(&mut sheet, &mut engine, "x = x + 1").unwrap_err()), cycle(&["x", "x"]));
+        assert_eq!(engine.get_var("a").map(|v| float(&v)), Some(1.));
+        // `a` still holds its value, so `b` and `c` still follow it
+        assert_eq!(define(&mut sheet, &mut engine, "a = 2").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn a_cycle_through_a_function_needs_a_variable() {
+        let (mut sheet, mut engine) = (Sheet::default(), Engine::new());
+        define(&mut sheet, &mut engine, "k = 1").unwrap();
+        define(&mut sheet, &mut engine, "f(x) = x + k").unwrap();
+        let e = define(&mut sheet, &mut engine, "k = f(1)").unwrap_err();
+        assert_eq!(kind(e), cycle(&["k", "f", "k"]));
+    }
+
+    #[test]
+    fn functions_may_call_themselves() {
+        let (mut sheet, mut engine) = (Sheet::default(), Engine::new());
+        define(&mut sheet, &mut engine, "fact(n) = if n < 2 then 1 else n * fact(n - 1)").unwrap();
+        define(&mut sheet, &mut engine, "y = fact(5)").unwrap();
+        assert_eq!(engine.get_var("y").map(|v| float(&v)), Some(120.));
+    }
+
+    #[test]
+    fn a_forgotten_variable_is_no_longer_recomputed() {
+        let (mut sheet, mut engine) = (Sheet::default(), Engine::new());
+        for source in ["a = 1", "b = a + 1"] {
+            define(&mut sheet, &mut engine, source).unwrap();
+        }
+        sheet.forget("b");
+        assert_eq!(define(&mut sheet, &mut engine, "a = 5").unwrap(), []);
+    }
+}
@@ -1,23 +1,40 @@
 use rustyline::{DefaultEditor, error::ReadlineError};
-use std::collections::HashMap;
 
-use crate::{
-    ast::Parser,
-    eval::*,
-    lexer::{Lexer, Token},
-    error::*,
+use simple_math::{
+    Binding, CONSTANTS, Context, Diagnose, Diagnostic, Engine, EngineResult, LexerError, Mode, Parser, ParsingError,
+    Rounding, Token, TokenType, is_assignment,
+    reactive::{Sheet, Update},
+    solve, tokenize_from,
 };
 
+/// Printed after each result.
+const SEPARATOR: &str = "<-------------------------->";
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct REPL {
-    vars: HashMap<String, f64>,
+    engine: Engine,
     rl: DefaultEditor,
+    color: bool,
+    /// Whether assignments stay live, recomputed when what they use changes.
+    reactive: bool,
+    sheet: Sheet,
+    /// Every line lexed so far, each ending in a newline. Spans point
+    /// into it, so an error from a function defined lines ago is shown
+    /// on the line that defined it.
+    transcript: String,
 }
 
 impl REPL {
-    pub fn new() -> Self {
+    pub fn new(color: bool, mode: Mode, reactive: bool, solver: solve::Options) -> Self {
+        let mut engine = Engine::with_mode(mode);
+        engine.set_solver(solver);
         Self {
-            vars: HashMap::new(),
+            engine,
             rl: DefaultEditor::new().unwrap(),
+            color,
+            reactive,
+            sheet: Sheet::default(),
+            transcript: String::new(),
         }
     }
 
@@ -29,69 +46,96 @@ impl REPL {
                     return true;
                 }
 
-                let tk = self.tokenize(line.as_str());
+                if let Some(command) = line.trim().strip_prefix(':') {
+                    self.command(command);
+                    self.rl.add_history_entry(&line).unwrap();
+                    return false;
+                }
+
+                let tk = self.tokenize(&line);
                 let tk = match tk {
                     Ok(t) => t,
                     Err(e) => {
-                        eprintln!("Lexer Error: {e}");
+                        self.report(&e);
                         self.rl.add_history_entry(&line).unwrap();
                         return false;
                     }
                 };
 
+                // blank or comment-only line
+                if tk.iter().all(|t| t.token_type == TokenType::EndExpr) {
+                    self.rl.add_history_entry(&line).unwrap();
+                    return false;
+                }
+
+                let is_assign = is_assignment(&tk);
                 let mut parser = Parser::new(tk);
-                if line.contains("=") {
+                if is_assign {
                     let assign = match parser.parse_assign() {
                         Ok(p) => p,
                         Err(e) => {
-                            eprintln!("Parsing Error: {e}");
+                            self.report(&e);
                             self.rl.add_history_entry(&line).unwrap();
                             return false;
                         }
                     };
 
-                    let (n, v) = match eval_assign(assign, &self.vars) {
-                        Ok(tuple) => tuple,
+                    let name = assign.name.clone();
+                    let mode = self.engine.mode();
+                    let result = match self.reactive {
+                        true => self.sheet.define(assign, &line, &mut self.engine),
+                        false => {
+                            self.sheet.forget(&name);
+                            self.engine.define(assign).map(|_| Vec::new())
+                        }
+                    };
+                    self.report_warnings();
+                    match result {
+                        Ok(updates) => {
+                            println!("< {name} = {}", show(mode, self.engine.get(&name).expect("just defined")));
+                            self.show_updates(updates);
+                        }
                         Err(e) => {
-                            eprintln!("Eval Error: {e}");
+                            self.report(&e);
                             self.rl.add_history_entry(&line).unwrap();
                             return false;
                         }
-                    };
-                    println!("< {n} = {v}");
-                    self.vars.insert(n, v);
+                    }
                 } else {
-                    if line.chars().all(|c| c.is_alphabetic() || c == '_') {
-                        let v = self.vars.get(&line);
-                        match v {
-                            Some(a) => println!("= {a}"),
-                            None => eprintln!("This variable does not exist"),
-                        }
+                    // a bound name shows what it is bound to, functions included
+                    let binding = match line.chars().all(|c| c.is_alphabetic() || c == '_') {
+                        true => self.engine.get(&line),
+                        false => None,
+                    };
+                    if let Some(a) = binding {
+                        println!("= {}", show(self.engine.mode(), a));
                     } else {
-                        let expr = match parser.parse_expr_pratt(0.){
+                        let expr = match parser.parse_expression() {
                             Ok(p) => p,
                             Err(e) => {
-                                eprintln!("Parsing Error: {e}");
+                                self.report(&e);
                                 self.rl.add_history_entry(&line).unwrap();
                                 return false;
                             }
                         };
-                        let res = match eval_expr(expr, &self.vars){
+                        let res = self.engine.eval_expr(expr);
+                        self.report_warnings();
+                        let res = match res {
                             Ok(r) => r,
                             Err(e) => {
-                                eprintln!("Eval Error: {e}");
+                                self.report(&e);
                                 self.rl.add_history_entry(&line).unwrap();
                                 return false;
                             }
                         };
 
-                        println!("= {res}");
+                        println!("= {}", self.engine.mode().show(&res));
                     }
                 }
 
                 self.rl.add_history_entry(&line).unwrap();
 
-                println!("<-------------------------->");
+                println!("{SEPARATOR}");
                 false
             }
             Err(ReadlineError::Interrupted) => true,
@@ -103,13 +147,149 @@ impl REPL {
         }
     }
 
-    pub fn tokenize(&self, s: &str) -> Result<Vec<Token>, LexerError> {
-        let lex = Lexer::new(s);
-        let mut vlex = vec![];
-        for t in lex {
-            let t = t?;
-            vlex.push(t);
+    /// Runs a `:command` line.
+    fn command(&mut self, command: &str) {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("precision" | "mode"), None, _) => println!("= {}", self.engine.mode()),
+            (Some("constants"), None, _) => {
+                for c in CONSTANTS {
+                    let shadowed = if self.engine.get(c.name).is_some() { " (shadowed)" } else { "" };
+                    println!("{:<4} = {:<18} {}{shadowed}", c.name, c.value, c.description);
+                }
+            }
+            (Some("history"), None, _) => {
+                for (name, d) in self.engine.definitions() {
+                    let times = self.engine.history(name).len();
+                    let redefined = if times > 1 { format!(" (defined {times} times)") } else { String::new() };
+                    println!("{name} = {}{redefined}", show(self.engine.mode(), &d.binding));
+                }
+            }
+            (Some("history"), Some(name), None) => {
+                let history = self.engine.history(name);
+                if history.is_empty() {
+                    return self.error(&format!("`{name}` was never defined"));
+                }
+                // definitions are numbered across the session, oldest first
+                for d in history {
+                    println!("#{:<3} {name} = {}", d.order + 1, show(self.engine.mode(), &d.binding));
+                }
+            }
+            (Some("diff"), Some(_), Some(_)) => self.diff(command["diff".len()..].trim()),
+            (Some("solver"), None, _) => {
+                let solver = self.engine.solver();
+                println!("= tolerance {:e}, at most {} iterations", solver.tolerance, solver.max_iterations);
+            }
+            (Some("solver"), Some(setting), Some(value)) => {
+                let mut solver = self.engine.solver();
+                match (setting, value.parse::<f64>()) {
+                    ("tolerance", Ok(t)) if t > 0. => solver.tolerance = t,
+                    ("iterations", Ok(n)) if n >= 1. && n.fract() == 0. => solver.max_iterations = n as usize,
+                    _ => return self.error("Usage: :solver tolerance <positive number>, or :solver iterations <count>"),
+                }
+                self.engine.set_solver(solver);
+            }
+            (Some("solve"), Some(_), Some(_)) => self.solve(command["solve".len()..].trim()),
+            (Some("reactive"), None, _) => self.reactive = true,
+            (Some("reactive"), Some("off"), None) => self.reactive = false,
+            (Some("precision" | "complex"), Some("off"), None) => self.engine.set_mode(Mode::Float),
+            (Some("complex"), None, _) => self.engine.set_mode(Mode::Complex { polar: false }),
+            (Some("complex"), Some("polar"), None) => self.engine.set_mode(Mode::Complex { polar: true }),
+            (Some("precision"), Some(digits), rounding) => {
+                let digits = digits.parse::<u32>().ok().filter(|d| *d > 0);
+                let rounding = rounding.map_or(Some(Rounding::default()), Rounding::parse);
+                match (digits, rounding) {
+                    (Some(d), Some(r)) => self.engine.set_mode(Mode::Decimal(Context::new(d, r))),
+                    _ => self.error("Usage: :precision <digits> [half-even|half-up|down], or :precision off"),
+                }
+            }
+            _ => self.error(&format!("Unknown command :{command}")),
+        }
+    }
+
+    /// `:diff pi*r^2 r`: prints the derivative of the expression with
+    /// respect to the name after it.
+    fn diff(&mut self, args: &str) {
+        let (source, var) = args.rsplit_once(char::is_whitespace).expect("two words at least");
+        if !var.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return self.error("Usage: :diff <expression> <variable>");
+        }
+        let derivative = self.parse(source, Parser::parse_expression).and_then(|e| self.engine.diff(&e, var));
+        match derivative {
+            Ok(d) => println!("= {}\n{SEPARATOR}", d.to_source()),
+            Err(e) => self.report(&e),
+        }
+    }
+
+    /// `:solve pi*r^2 = 10 r`: prints the value of the name at the end
+    /// that makes both sides of the equation equal.
+    fn solve(&mut self, args: &str) {
+        let (source, var) = args.rsplit_once(char::is_whitespace).expect("two words at least");
+        if !var.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return self.error("Usage: :solve <expression> = <expression> <variable>");
+        }
+        let root = self.parse(source, Parser::parse_equation).and_then(|(lhs, rhs)| self.engine.solve(lhs, rhs, var));
+        self.report_warnings();
+        match root {
+            Ok(v) => println!("= {}\n{SEPARATOR}", self.engine.mode().show(&v)),
+            Err(e) => self.report(&e),
+        }
+    }
+
+    fn error(&self, message: &str) {
+        eprint!("{}", Diagnostic::new(message, None).render("", "", self.color));
+    }
+
+    /// Adds `line` to the transcript and lexes it there.
+    fn tokenize(&mut self, line: &str) -> Result<Vec<Token>, LexerError> {
+        let (start, number) = (self.transcript.len(), self.transcript.matches('\n').count() + 1);
+        self.transcript.push_str(line);
+        let tokens = tokenize_from(&self.transcript, start, number);
+        self.transcript.push('\n');
+        tokens
+    }
+
+    /// Lexes `source` into the transcript and parses it with `parse`.
+    fn parse<T>(&mut self, source: &str, parse: fn(&mut Parser) -> Result<T, ParsingError>) -> EngineResult<T> {
+        let tokens = self.tokenize(source)?;
+        Ok(parse(&mut Parser::new(tokens))?)
+    }
+
+    /// Shows an error on the line it comes from.
+    fn report(&self, e: &impl Diagnose) {
+        eprint!("{}", e.diagnostic().render("<repl>", &self.transcript, self.color));
+    }
+
+    /// Prints the variables that a reactive assignment recomputed, when
+    /// their value changed.
+    fn show_updates(&self, updates: Vec<Update>) {
+        let mode = self.engine.mode();
+        for update in updates {
+            match update.after {
+                Ok(after) => {
+                    let (before, after) = (update.before.map(|v| mode.show(&v)), mode.show(&after));
+                    match before {
+                        Some(before) if before == after => {}
+                        Some(before) => println!("< {} = {after} (was {before})", update.name),
+                        None => println!("< {} = {after}", update.name),
+                    }
+                }
+                Err(e) => self.report(&e),
+            }
+        }
+    }
+
+    fn report_warnings(&self) {
+        for warning in self.engine.take_warnings() {
+            self.report(&warning);
         }
-        Ok(vlex)
+    }
+}
+
+/// A binding as `mode` prints it.
+fn show(mode: Mode, b: &Binding) -> String {
+    match b {
+        Binding::Value(v) => mode.show(v),
+        b => b.to_string(),
     }
 }
@@ -0,0 +1,292 @@
+/// How hard `solve` tries before giving up.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// How close two successive guesses must be, relative to their size
+    /// (or absolutely, below 1), for the answer to count as found.
+    pub tolerance: f64,
+    /// How many steps refining the answer may take.
+    pub max_iterations: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { tolerance: 1e-12, max_iterations: 100 }
+    }
+}
+
+/// Where to look for a root.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Start {
+    /// Near this value, first looking outward from it for a range where
+    /// the function changes sign.
+    Guess(f64),
+    /// Between these two values, in either order, where the function must
+    /// change sign.
+    Range(f64, f64),
+}
+
+/// Why no root was found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Failure {
+    /// The function has the same sign at both ends of a given range.
+    NoSignChange,
+    /// The guesses didn't settle within the iteration limit.
+    NotConverged,
+}
+
+/// A real function, `None` where it has no finite value.
+pub(crate) type Function<'a> = dyn Fn(f64) -> Option<f64> + 'a;
+
+/// How many times the search outward from a guess doubles its step, so
+/// that roots up to about 10^17 times the guess away are found.
+const MAX_DOUBLINGS: i32 = 64;
+
+/// A root of `f`, using its derivative `slope` for Newton steps when
+/// there is one and secant steps otherwise. Inside a range where `f`
+/// changes sign, a step that would leave the range bisects it instead,
+/// so that the search can't diverge.
+pub(crate) fn root(f: &Function<'_>, slope: Option<&Function<'_>>, start: Start, options: Options) -> Result<f64, Failure> {
+    let finder = Finder { f, slope, options };
+    match start {
+        Start::Range(a, b) => match (f(a.min(b)), f(a.max(b))) {
+            (Some(fl), Some(fh)) if fl == 0. || fh == 0. || (fl < 0.) != (fh < 0.) => {
+                finder.bracketed(a.min(b), a.max(b), (a + b) / 2.)
+            }
+            _ => Err(Failure::NoSignChange),
+        },
+        Start::Guess(guess) => match finder.bracket(guess) {
+            Some((low, high)) => finder.bracketed(low, high, guess),
+            // a root where `f` touches 0 without crossing it, like that of `x^2`
+            None => finder.open(guess),
+        },
+    }
+}
+
+struct Finder<'a> {
+    f: &'a Function<'a>,
+    slope: Option<&'a Function<'a>>,
+    options: Options,
+}
+
+impl Finder<'_> {
+    /// The next guess after `x`: a Newton step, or a secant step through
+    /// `previous` when there is no derivative.
+    fn step(&self, x: f64, fx: f64, previous: (f64, f64)) -> f64 {
+        let slope = match self.slope {
+            Some(slope) => slope(x),
+            None => Some((fx - previous.1) / (x - previous.0)),
+        };
+        match slope {
+            Some(s) if s != 0. => x - fx / s,
+            _ => f64::NAN,
+        }
+    }
+
+    fn settled(&self, x: f64, next: f64) -> bool {
+        (next - x).abs() <= self.options.tolerance * next.abs().max(1.)
+    }
+
+    /// A range around `guess` where `f` changes sign, widening it on
+    /// both sides until one is found.
+    fn bracket(&self, guess: f64) -> Option<(f64, f64)> {
+        let first = (self.f)(guess);
+        if first == Some(0.) {
+            return Some((guess, guess));
+        }
+        let step = guess.abs().max(1.) / 100.;
+        // the last finite point on the right and on the left, and whether
+        // it is where `f` stops being finite
+        let mut sides = [(first.map(|f| (guess, f)), false); 2];
+        for k in 0..MAX_DOUBLINGS {
+            let d = step * 2f64.powi(k);
+            // upward first, as the answers asked for are more often positive
+            for ((side, at_edge), x) in sides.iter_mut().zip([guess + d, guess - d]) {
+                let ((x, fx), edge) = match ((self.f)(x), *side) {
+                    (Some(fx), _) => ((x, fx), false),
+                    // a root may lie just before the edge, as for `exp(x) = 1e300`
+                    (None, Some(last)) if !*at_edge => (self.edge(last, x), true),
+                    (None, _) => continue,
+                };
+                *at_edge = edge;
+                if let Some((last, fl)) = *side
+                    && (fx == 0. || (fl < 0.) != (fx < 0.))
+                {
+                    return Some((last.min(x), last.max(x)));
+                }
+                *side = Some((x, fx));
+            }
+        }
+        None
+    }
+
+    /// The point closest to `beyond`, where `f` isn't finite, at which
+    /// `f` still is, by bisecting between it and `finite`.
+    fn edge(&self, mut finite: (f64, f64), mut beyond: f64) -> (f64, f64) {
+        loop {
+            let middle = (finite.0 + beyond) / 2.;
+            if middle == finite.0 || middle == beyond {
+                return finite;
+            }
+            match (self.f)(middle) {
+                Some(fm) => finite = (middle, fm),
+                None => beyond = middle,
+            }
+        }
+    }
+
+    /// Refines a root between `low` and `high`, where `f` changes sign,
+    /// starting from `x`.
+    fn bracketed(&self, mut low: f64, mut high: f64, x: f64) -> Result<f64, Failure> {
+        let f_low = (self.f)(low).ok_or(Failure::NotConverged)?;
+        let f_high = (self.f)(high).ok_or(Failure::NotConverged)?;
+        if f_low == 0. {
+            return Ok(low);
+        }
+        if f_high == 0. {
+            return Ok(high);
+        }
+        let negative_low = f_low < 0.;
+        let mut x = if low < x && x < high { x } else { (low + high) / 2. };
+        let mut previous = (low, f_low);
+        for _ in 0..self.options.max_iterations {
+            let Some(fx) = (self.f)(x) else {
+                x = (low + high) / 2.;
+                continue;
+            };
+            if fx == 0. {
+                return Ok(x);
+            }
+            match (fx < 0.) == negative_low {
+                true => low = x,
+                false => high = x,
+            }
+            let mut next = self.step(x, fx, previous);
+            if !(low < next && next < high) {
+                next = (low + high) / 2.;
+            }
+            if self.settled(x, next) || self.settled(low, high) {
+                // a pole between the ends changes sign too, but doesn't get closer to 0
+                return match (self.f)(next) {
+                    Some(y) if y.abs() <= f_low.abs().max(f_high.abs()) => Ok(next),
+                    _ => Err(Failure::NotConverged),
+                };
+            }
+            previous = (x, fx);
+            x = next;
+        }
+        Err(Failure::NotConverged)
+    }
+
+    /// Newton or secant steps from `x` with nothing to keep them in a range.
+    fn open(&self, mut x: f64) -> Result<f64, Failure> {
+        let start = (self.f)(x).ok_or(Failure::NotConverged)?;
+        let nudge = x + x.abs().max(1.) * 1e-4;
+        let mut previous = (nudge, (self.f)(nudge).ok_or(Failure::NotConverged)?);
+        for _ in 0..self.options.max_iterations {
+            let fx = (self.f)(x).ok_or(Failure::NotConverged)?;
+            if fx == 0. {
+                return Ok(x);
+            }
+            let next = self.step(x, fx, previous);
+            if !next.is_finite() {
+                return Err(Failure::NotConverged);
+            }
+            if self.settled(x, next) {
+                return match (self.f)(next) {
+                    Some(y) if y.abs() <= start.abs() => Ok(next),
+                    _ => Err(Failure::NotConverged),
+                };
+            }
+            previous = (x, fx);
+            x = next;
+        }
+        Err(Failure::NotConverged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(f: impl Fn(f64) -> f64, start: Start) -> Result<f64, Failure> {
+        let f = move |x: f64| Some(f(x)).filter(|y| y.is_finite());
+        root(&f, None, start, Options::default())
+    }
+
+    #[test]
+    fn widens_the_search_until_the_sides_cross() {
+        let area = |r: f64| std::f64::consts::PI * r * r - 50.;
+        let r = solve(area, Start::Guess(1.)).unwrap();
+        assert!((r - 3.989422804014327).abs() < 1e-12);
+        // far from the guess, after many doublings of the step
+        let x = solve(|x| x - 1e6, Start::Guess(0.)).unwrap();
+        assert!((x - 1e6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn prefers_the_root_above_the_guess() {
+        assert!((solve(|x| x * x - 2., Start::Guess(0.)).unwrap() - 2f64.sqrt()).abs() < 1e-12);
+        assert!((solve(|x| x * x - 2., Start::Guess(-1.)).unwrap() + 2f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn newton_steps_use_the_slope() {
+        let f = |x: f64| Some(x.cos() - x);
+        let slope = |x: f64| Some(-x.sin() - 1.);
+        let x = root(&f, Some(&slope), Start::Guess(1.), Options::default()).unwrap();
+        assert!((x - 0.7390851332151607).abs() < 1e-12);
+    }
+
+    #[test]
+    fn a_root_that_touches_zero_is_found_without_a_range() {
+        let x = solve(|x| x * x, Start::Guess(1.)).unwrap();
+        assert!(x.abs() < 1e-9);
+        let x = solve(|x| (x - 3.).powi(2), Start::Guess(1.)).unwrap();
+        assert!((x - 3.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn a_pole_is_not_a_root() {
+        assert_eq!(solve(|x| 1. / x, Start::Guess(1.)), Err(Failure::NotConverged));
+        assert_eq!(solve(|x| 1. / x, Start::Range(-1., 2.)), Err(Failure::NotConverged));
+    }
+
+    #[test]
+    fn no_real_root_does_not_converge() {
+        assert_eq!(solve(|x| x * x + 1., Start::Guess(1.)), Err(Failure::NotConverged));
+    }
+
+    #[test]
+    fn a_range_must_change_sign() {
+        assert_eq!(solve(|x| x * x * x - 5., Start::Range(0., 1.)), Err(Failure::NoSignChange));
+        let x = solve(|x| x.sin() - 0.5, Start::Range(0., 1.)).unwrap();
+        assert!((x - std::f64::consts::FRAC_PI_6).abs() < 1e-12);
+        assert_eq!(solve(|x| x - 1., Start::Range(1., 4.)), Ok(1.));
+    }
+
+    #[test]
+    fn a_root_just_before_overflow_is_bracketed() {
+        let x = solve(|x| x.exp() - 1e300, Start::Guess(1.)).unwrap();
+        assert!((x - 1e300f64.ln()).abs() < 1e-9);
+        // and just after `f` starts being finite, below the guess
+        let x = solve(|x| x.sqrt() - 1e-3, Start::Guess(1.)).unwrap();
+        assert!((x - 1e-6).abs() < 1e-15);
+    }
+
+    #[test]
+    fn a_range_may_be_given_high_first() {
+        let x = solve(|x| x * x - 2., Start::Range(2., 0.)).unwrap();
+        assert!((x - 2f64.sqrt()).abs() < 1e-12);
+        assert_eq!(solve(|x| x - 1., Start::Range(4., 1.)), Ok(1.));
+        assert_eq!(solve(|x| x * x * x - 5., Start::Range(1., 0.)), Err(Failure::NoSignChange));
+    }
+
+    #[test]
+    fn the_iteration_limit_applies() {
+        let f = |x: f64| Some(x.powf(x) - 10.).filter(|y| y.is_finite());
+        let options = Options { max_iterations: 2, ..Options::default() };
+        assert_eq!(root(&f, None, Start::Guess(1.), options), Err(Failure::NotConverged));
+        let x = root(&f, None, Start::Guess(1.), Options::default()).unwrap();
+        assert!((x - 2.5061841455887692).abs() < 1e-10);
+    }
+}
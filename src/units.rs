@@ -0,0 +1,311 @@
+use std::fmt;
+
+use crate::rational::Rational;
+
+/// Exponents of the SI base units, in the order of `BASE_UNITS`.
+pub type Dimension = [i32; 7];
+
+pub const BASE_UNITS: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+const NONE: Dimension = [0, 0, 0, 0, 0, 0, 0];
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0];
+const AREA: Dimension = [2, 0, 0, 0, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0];
+const FREQUENCY: Dimension = [0, 0, -1, 0, 0, 0, 0];
+const SPEED: Dimension = [1, 0, -1, 0, 0, 0, 0];
+const FORCE: Dimension = [1, 1, -2, 0, 0, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0, 0, 0];
+const CURRENT: Dimension = [0, 0, 0, 1, 0, 0, 0];
+const CHARGE: Dimension = [0, 0, 1, 1, 0, 0, 0];
+const VOLTAGE: Dimension = [2, 1, -3, -1, 0, 0, 0];
+const RESISTANCE: Dimension = [2, 1, -3, -2, 0, 0, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 0, 1, 0, 0];
+const AMOUNT: Dimension = [0, 0, 0, 0, 0, 1, 0];
+const LUMINOSITY: Dimension = [0, 0, 0, 0, 0, 0, 1];
+
+/// The units a literal can be written in: name, size in SI base units (as
+/// a decimal literal, so it converts exactly) and dimension. Temperatures
+/// other than kelvin aren't here, as they don't start at zero.
+const UNITS: &[(&str, &str, Dimension)] = &[
+    ("m", "1", LENGTH),
+    ("km", "1e3", LENGTH),
+    ("cm", "1e-2", LENGTH),
+    ("mm", "1e-3", LENGTH),
+    ("um", "1e-6", LENGTH),
+    ("nm", "1e-9", LENGTH),
+    ("in", "0.0254", LENGTH),
+    ("ft", "0.3048", LENGTH),
+    ("yd", "0.9144", LENGTH),
+    ("mi", "1609.344", LENGTH),
+    ("ha", "1e4", AREA),
+    ("L", "1e-3", VOLUME),
+    ("mL", "1e-6", VOLUME),
+    ("kg", "1", MASS),
+    ("g", "1e-3", MASS),
+    ("mg", "1e-6", MASS),
+    ("t", "1e3", MASS),
+    ("lb", "0.45359237", MASS),
+    ("oz", "0.028349523125", MASS),
+    ("s", "1", TIME),
+    ("ms", "1e-3", TIME),
+    ("us", "1e-6", TIME),
+    ("min", "60", TIME),
+    ("h", "3600", TIME),
+    ("day", "86400", TIME),
+    ("Hz", "1", FREQUENCY),
+    ("kHz", "1e3", FREQUENCY),
+    ("MHz", "1e6", FREQUENCY),
+    ("mph", "0.44704", SPEED),
+    ("N", "1", FORCE),
+    ("kN", "1e3", FORCE),
+    ("Pa", "1", PRESSURE),
+    ("kPa", "1e3", PRESSURE),
+    ("MPa", "1e6", PRESSURE),
+    ("bar", "1e5", PRESSURE),
+    ("J", "1", ENERGY),
+    ("kJ", "1e3", ENERGY),
+    ("MJ", "1e6", ENERGY),
+    ("cal", "4.184", ENERGY),
+    ("kcal", "4184", ENERGY),
+    ("Wh", "3600", ENERGY),
+    ("kWh", "3.6e6", ENERGY),
+    ("W", "1", POWER),
+    ("kW", "1e3", POWER),
+    ("MW", "1e6", POWER),
+    ("A", "1", CURRENT),
+    ("mA", "1e-3", CURRENT),
+    ("C", "1", CHARGE),
+    ("V", "1", VOLTAGE),
+    ("kV", "1e3", VOLTAGE),
+    ("ohm", "1", RESISTANCE),
+    ("K", "1", TEMPERATURE),
+    ("mol", "1", AMOUNT),
+    ("cd", "1", LUMINOSITY),
+];
+
+/// A product of named units with integer exponents, like `km/h` or
+/// `kg*m/s^2`. No factors means a plain number. Every exponent, and every
+/// exponent of its dimension, fits an `i32`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Unit {
+    factors: Vec<(&'static str, i32)>,
+}
+
+/// A unit, or a base unit of its dimension, with an exponent too large
+/// for a `Unit`, like the `m^4294967294` of `(1 m^2147483647)^2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overflow(pub &'static str, pub i64);
+
+impl Unit {
+    pub fn named(name: &str) -> Option<Self> {
+        let &(name, ..) = UNITS.iter().find(|u| u.0 == name)?;
+        Some(Self { factors: vec![(name, 1)] })
+    }
+
+    /// The SI base units of a dimension, e.g. `kg*m/s^2` for a force.
+    pub fn base(dim: Dimension) -> Self {
+        let factors = BASE_UNITS.iter().zip(dim).filter(|(_, e)| *e != 0).map(|(&n, e)| (n, e)).collect();
+        Self { factors }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.factors.is_empty()
+    }
+
+    /// A unit of `factors`, without those to the power 0.
+    fn of(factors: Vec<(&'static str, i64)>) -> Result<Unit, Overflow> {
+        let factors = factors
+            .into_iter()
+            .filter(|f| f.1 != 0)
+            .map(|(name, exp)| i32::try_from(exp).map(|e| (name, e)).map_err(|_| Overflow(name, exp)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut dim = [0i64; 7];
+        for &(name, exp) in &factors {
+            for (d, u) in dim.iter_mut().zip(lookup(name).2) {
+                *d += i64::from(u) * i64::from(exp);
+            }
+        }
+        match BASE_UNITS.iter().zip(dim).find(|(_, d)| i32::try_from(*d).is_err()) {
+            Some((&name, d)) => Err(Overflow(name, d)),
+            None => Ok(Unit { factors }),
+        }
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        let mut dim = NONE;
+        for &(name, exp) in &self.factors {
+            for (d, u) in dim.iter_mut().zip(lookup(name).2) {
+                *d += u * exp;
+            }
+        }
+        dim
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        self.dimension() == NONE
+    }
+
+    /// How many SI base units one of this is, e.g. 1000/3600 for `km/h`.
+    pub fn scale(&self) -> Rational {
+        self.factors.iter().fold(Rational::from_int(1i64), |acc, &(name, exp)| {
+            let size = Rational::parse(lookup(name).1).expect("unit sizes are valid literals");
+            acc * size.pow(exp as i64).expect("unit sizes aren't zero")
+        })
+    }
+
+    pub fn mul(&self, other: &Unit) -> Result<Unit, Overflow> {
+        let mut factors = self.factors.iter().map(|&(n, e)| (n, i64::from(e))).collect::<Vec<_>>();
+        for &(name, exp) in &other.factors {
+            match factors.iter_mut().find(|f| f.0 == name) {
+                Some(f) => f.1 += i64::from(exp),
+                None => factors.push((name, i64::from(exp))),
+            }
+        }
+        Unit::of(factors)
+    }
+
+    /// `other` with each factor written in the unit `self` uses for the
+    /// same dimension, so that `m/s` times `min` can cancel into `m`.
+    pub fn aligned(&self, other: &Unit) -> Result<Unit, Overflow> {
+        let factors = other
+            .factors
+            .iter()
+            .map(|&(name, exp)| {
+                let dim = lookup(name).2;
+                match self.factors.iter().find(|f| lookup(f.0).2 == dim) {
+                    Some(&(same, _)) => (same, exp),
+                    None => (name, exp),
+                }
+            })
+            .collect();
+        Unit::default().mul(&Unit { factors })
+    }
+
+    pub fn div(&self, other: &Unit) -> Result<Unit, Overflow> {
+        self.mul(&other.powi(-1)?)
+    }
+
+    pub fn powi(&self, exp: i32) -> Result<Unit, Overflow> {
+        Unit::of(self.factors.iter().map(|&(n, e)| (n, i64::from(e) * i64::from(exp))).collect())
+    }
+
+    /// The unit to the power `exp`, when every exponent stays whole and
+    /// fits, so `m^2` has a square root but `m` doesn't.
+    pub fn pow(&self, exp: f64) -> Option<Unit> {
+        let factors = self
+            .factors
+            .iter()
+            .map(|&(n, e)| {
+                let e = e as f64 * exp;
+                let whole = (e - e.round()).abs() < 1e-9 && e.abs() <= i32::MAX as f64;
+                whole.then_some((n, e.round() as i64))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Unit::of(factors).ok()
+    }
+}
+
+fn lookup(name: &str) -> &'static (&'static str, &'static str, Dimension) {
+    UNITS.iter().find(|u| u.0 == name).expect("units are built from known names")
+}
+
+/// `m/s^2`: the factors with positive exponents joined by `*`, then those
+/// with negative ones after a `/` each.
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let factor = |f: &mut fmt::Formatter<'_>, name: &str, exp: i32| match exp {
+            1 => write!(f, "{name}"),
+            e => write!(f, "{name}^{e}"),
+        };
+        let mut first = true;
+        for &(name, exp) in self.factors.iter().filter(|f| f.1 > 0) {
+            if !first {
+                write!(f, "*")?;
+            }
+            factor(f, name, exp)?;
+            first = false;
+        }
+        if first {
+            write!(f, "1")?;
+        }
+        for &(name, exp) in self.factors.iter().filter(|f| f.1 < 0) {
+            write!(f, "/")?;
+            factor(f, name, -exp)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `name` to the power `exp`.
+    fn unit(name: &str, exp: i32) -> Unit {
+        Unit::named(name).unwrap().powi(exp).unwrap()
+    }
+
+    fn product(units: &[Unit]) -> Unit {
+        units.iter().fold(Unit::default(), |acc, u| acc.mul(u).unwrap())
+    }
+
+    #[test]
+    fn scale_is_the_size_in_base_units() {
+        assert_eq!(unit("km", 1).scale(), Rational::from_int(1000i64));
+        let km_per_h = unit("km", 1).div(&unit("h", 1)).unwrap();
+        assert_eq!(km_per_h.to_string(), "km/h");
+        assert_eq!(km_per_h.scale(), Rational::new(5i64.into(), 18i64.into()));
+        assert_eq!(unit("cm", 2).scale(), Rational::parse("1e-4").unwrap());
+        assert_eq!(unit("mph", 1).dimension(), unit("m", 1).div(&unit("s", 1)).unwrap().dimension());
+    }
+
+    #[test]
+    fn dimensions_add_up_over_factors() {
+        let force = product(&[unit("kg", 1), unit("m", 1), unit("s", -2)]);
+        assert_eq!(force.dimension(), FORCE);
+        assert_eq!(force.to_string(), "kg*m/s^2");
+        assert_eq!(Unit::base(unit("kWh", 1).dimension()).to_string(), "m^2*kg/s^2");
+        assert!(unit("km", 1).div(&unit("m", 1)).unwrap().is_dimensionless());
+        assert!(unit("m", 1).div(&unit("m", 1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn aligned_units_cancel() {
+        let speed = unit("m", 1).div(&unit("s", 1)).unwrap();
+        let aligned = speed.aligned(&unit("min", 1)).unwrap();
+        assert_eq!(aligned.to_string(), "s");
+        assert_eq!(speed.mul(&aligned).unwrap().to_string(), "m");
+        // factors of another dimension are left alone, and merged ones add up
+        let mixed = product(&[unit("km", 1), unit("mm", 2), unit("kg", 1)]);
+        assert_eq!(speed.aligned(&mixed).unwrap().to_string(), "m^3*kg");
+    }
+
+    #[test]
+    fn pow_keeps_exponents_whole() {
+        assert_eq!(unit("m", 2).pow(0.5).unwrap().to_string(), "m");
+        assert_eq!(unit("m", 1).pow(0.5), None);
+        assert_eq!(unit("m", 3).pow(-1. / 3.).unwrap().to_string(), "1/m");
+        assert!(unit("s", 4).pow(0.).unwrap().is_none());
+    }
+
+    #[test]
+    fn exponents_that_overflow_are_errors() {
+        // the seconds of a newton are squared
+        assert_eq!(unit("m", 1).powi(2_000_000_000).unwrap().to_string(), "m^2000000000");
+        assert_eq!(Unit::named("N").unwrap().powi(2_000_000_000), Err(Overflow("s", -4_000_000_000)));
+        let big = unit("m", i32::MAX);
+        assert_eq!(big.mul(&unit("m", 1)), Err(Overflow("m", 1 << 31)));
+        assert_eq!(big.div(&unit("m", -1)), Err(Overflow("m", 1 << 31)));
+        assert_eq!(unit("m", i32::MIN).powi(-1), Err(Overflow("m", 1 << 31)));
+        assert_eq!(unit("m", 1).pow(1e10), None);
+        assert_eq!(unit("m", 65536).pow(65536.), None);
+        // aligning merges `m` and `km` into more `m` than fit
+        let length = product(&[unit("ha", -(1 << 30)), unit("m", i32::MAX), unit("km", 1)]);
+        assert_eq!(length.dimension(), NONE);
+        assert_eq!(unit("m", 1).aligned(&length), Err(Overflow("m", 1 << 31)));
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+use crate::{
+    complex::{Complex, Polar},
+    decimal::{Context, Decimal},
+    lexer::Number,
+    rational::Rational,
+    units::Unit,
+};
+
+/// Decimal places shown after an exact fraction, as in `1/3 ≈ 0.333…`.
+pub const EXPANSION_DIGITS: usize = 20;
+
+/// How number literals are read, and so what arithmetic on them means.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mode {
+    /// 64-bit floating point, like the compiled program.
+    #[default]
+    Float,
+    /// Arbitrary-size fractions; `+ - * /` never round.
+    Exact,
+    /// Decimal numbers with as many significant digits as asked for.
+    Decimal(Context),
+    /// Complex floating point numbers, where `i` is the imaginary unit and
+    /// `sqrt(-1)` has an answer. `polar` shows results as `r ∠ θ°`.
+    Complex { polar: bool },
+}
+
+impl Mode {
+    pub fn literal(self, n: &Number) -> Value {
+        match self {
+            _ if n.imaginary => Value::Complex(Complex::new(0., n.value)),
+            Mode::Complex { .. } => Value::Complex(Complex::real(n.value)),
+            Mode::Float => Value::Float(n.value),
+            Mode::Exact => Rational::parse(&n.text).map_or(Value::Float(n.value), Value::Exact),
+            Mode::Decimal(ctx) => ctx.parse(&n.text).map_or(Value::Float(n.value), |d| Value::Decimal(d, ctx)),
+        }
+    }
+
+    /// An integer in this mode, e.g. the 1 or 0 a comparison gives.
+    pub fn int(self, n: i64) -> Value {
+        match self {
+            Mode::Float => Value::Float(n as f64),
+            Mode::Exact => Value::Exact(Rational::from_int(n)),
+            Mode::Decimal(ctx) => Value::Decimal(Decimal::from_int(n), ctx),
+            Mode::Complex { .. } => Value::Complex(Complex::real(n as f64)),
+        }
+    }
+
+    /// How results are printed in this mode.
+    pub fn show(self, v: &Value) -> String {
+        match (self, v) {
+            (Mode::Complex { polar: true }, Value::Complex(c)) => Polar(*c).to_string(),
+            _ => format!("{v:#}"),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Float => write!(f, "floating point"),
+            Mode::Exact => write!(f, "exact fractions"),
+            Mode::Decimal(ctx) => write!(f, "{} significant digits, rounding {}", ctx.precision, ctx.rounding),
+            Mode::Complex { polar: false } => write!(f, "complex numbers"),
+            Mode::Complex { polar: true } => write!(f, "complex numbers, shown in polar form"),
+        }
+    }
+}
+
+/// What an expression evaluates to. Mixing any value with a complex one
+/// gives a complex, otherwise with a float gives a float, and an exact
+/// value with a decimal gives a decimal.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Float(f64),
+    Exact(Rational),
+    /// A decimal and the context it was computed in.
+    Decimal(Decimal, Context),
+    Complex(Complex),
+    /// A number of some unit, like `3 km`. The number is never a quantity
+    /// itself and the unit always has a dimension.
+    Quantity(Box<Value>, Unit),
+    /// A vector, or a matrix as a vector of rows. It is never empty and
+    /// all its elements have the same shape.
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// The number in the value's own unit, e.g. 3 for `3 km`. An array is
+    /// not one number, so it gives NaN.
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Float(v) => *v,
+            Value::Exact(r) => r.to_f64(),
+            Value::Decimal(d, _) => d.to_f64(),
+            Value::Complex(c) => c.re,
+            Value::Quantity(v, _) => v.to_f64(),
+            Value::Array(_) => f64::NAN,
+        }
+    }
+
+    pub fn to_complex(&self) -> Complex {
+        match self {
+            Value::Complex(c) => *c,
+            Value::Quantity(v, _) => v.to_complex(),
+            v => Complex::real(v.to_f64()),
+        }
+    }
+
+    /// Whether this is a complex number with an imaginary part.
+    pub fn is_nonreal(&self) -> bool {
+        match self {
+            Value::Complex(c) => !c.is_real(),
+            Value::Quantity(v, _) => v.is_nonreal(),
+            Value::Array(elements) => elements.iter().any(Value::is_nonreal),
+            _ => false,
+        }
+    }
+
+    /// The value as a decimal in `ctx`, unless it is a float.
+    pub fn to_decimal(&self, ctx: Context) -> Option<Decimal> {
+        match self {
+            Value::Float(_) | Value::Complex(_) | Value::Array(_) => None,
+            Value::Exact(r) => Some(ctx.rational(r)),
+            Value::Decimal(d, _) => Some(ctx.round(d.clone())),
+            Value::Quantity(v, _) => v.to_decimal(ctx),
+        }
+    }
+
+    pub fn decimal_context(&self) -> Option<Context> {
+        match self {
+            Value::Decimal(_, ctx) => Some(*ctx),
+            Value::Quantity(v, _) => v.decimal_context(),
+            _ => None,
+        }
+    }
+
+    /// How this value is more precise than a float, as in "has no exact
+    /// result", or `None` for floats.
+    pub fn precision_name(&self) -> Option<String> {
+        match self {
+            Value::Float(_) | Value::Complex(_) | Value::Array(_) => None,
+            Value::Exact(_) => Some("exact".to_string()),
+            Value::Decimal(_, ctx) => Some(format!("{}-digit", ctx.precision)),
+            Value::Quantity(v, _) => v.precision_name(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Value::Float(v) => *v == 0.,
+            Value::Exact(r) => r.is_zero(),
+            Value::Decimal(d, _) => d.is_zero(),
+            Value::Complex(c) => *c == Complex::default(),
+            Value::Quantity(v, _) => v.is_zero(),
+            Value::Array(elements) => elements.iter().all(Value::is_zero),
+        }
+    }
+
+    /// Conditions are plain numbers: anything but 0 and NaN is true. An
+    /// array is true when all its elements are, so `if a == b` works for
+    /// vectors.
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Float(v) => *v != 0. && !v.is_nan(),
+            Value::Exact(r) => !r.is_zero(),
+            Value::Decimal(d, _) => !d.is_zero(),
+            Value::Complex(c) => (c.re != 0. || c.im != 0.) && !c.re.is_nan() && !c.im.is_nan(),
+            Value::Quantity(v, _) => v.truthy(),
+            Value::Array(elements) => elements.iter().all(Value::truthy),
+        }
+    }
+
+    /// The lengths along each dimension: none for a number, `[3]` for a
+    /// vector of 3, `[2, 3]` for a matrix of 2 rows and 3 columns.
+    pub fn shape(&self) -> Vec<usize> {
+        match self {
+            Value::Array(elements) => {
+                let mut shape = vec![elements.len()];
+                shape.extend(elements[0].shape());
+                shape
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The value's unit, which has no factors for a plain number.
+    pub fn unit(&self) -> Unit {
+        match self {
+            Value::Quantity(_, unit) => unit.clone(),
+            _ => Unit::default(),
+        }
+    }
+
+    /// The number without its unit.
+    pub fn magnitude(self) -> Value {
+        match self {
+            Value::Quantity(v, _) => *v,
+            v => v,
+        }
+    }
+
+    /// The number as a quantity of `unit`. Dimensionless units, like the
+    /// `km/m` of `3 km / 1 m`, are multiplied in to give a plain number.
+    pub fn with_unit(self, unit: Unit) -> Value {
+        let v = self.magnitude();
+        match unit.is_dimensionless() {
+            true if unit.is_none() => v,
+            true => v.rescale(unit.scale()),
+            false => Value::Quantity(Box::new(v), unit),
+        }
+    }
+
+    /// The same amount in `unit`, which must have the same dimension.
+    pub fn convert(self, unit: &Unit) -> Value {
+        if self.unit() == *unit {
+            return self;
+        }
+        let ratio = self.unit().scale() / unit.scale();
+        self.magnitude().rescale(ratio).with_unit(unit.clone())
+    }
+
+    fn rescale(self, factor: Rational) -> Value {
+        match factor == Rational::from_int(1i64) {
+            true => self,
+            false => self * Value::Exact(factor),
+        }
+    }
+
+    fn combine(
+        self,
+        other: Value,
+        exact: fn(Rational, Rational) -> Rational,
+        decimal: fn(Context, &Decimal, &Decimal) -> Decimal,
+        complex: fn(Complex, Complex) -> Complex,
+        float: fn(f64, f64) -> f64,
+    ) -> Value {
+        match (self, other) {
+            (a @ Value::Complex(_), b) | (a, b @ Value::Complex(_)) => Value::Complex(complex(a.to_complex(), b.to_complex())),
+            (Value::Exact(a), Value::Exact(b)) => Value::Exact(exact(a, b)),
+            (a, b) => match (a.decimal_context(), b.decimal_context()) {
+                (Some(ctx), other) | (other, Some(ctx)) => {
+                    let ctx = other.map_or(ctx, |o| ctx.wider(o));
+                    match (a.to_decimal(ctx), b.to_decimal(ctx)) {
+                        (Some(x), Some(y)) => Value::Decimal(decimal(ctx, &x, &y), ctx),
+                        _ => Value::Float(float(a.to_f64(), b.to_f64())),
+                    }
+                }
+                _ => Value::Float(float(a.to_f64(), b.to_f64())),
+            },
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+impl From<Rational> for Value {
+    fn from(r: Rational) -> Self {
+        Value::Exact(r)
+    }
+}
+
+/// Adding or subtracting values of different dimensions panics; callers
+/// compare `unit().dimension()` first. So does multiplying or dividing
+/// values whose unit exponents would overflow, which callers check with
+/// `eval::multiply` and `eval::divide`. The result is in `self`'s unit.
+/// These work on single numbers; the evaluator takes arrays apart first.
+impl Add for Value {
+    type Output = Value;
+
+    fn add(self, other: Value) -> Value {
+        let unit = self.unit();
+        let other = other.convert(&unit).magnitude();
+        let sum = self.magnitude().combine(other, Rational::add, Context::add, Complex::add, f64::add);
+        sum.with_unit(unit)
+    }
+}
+
+impl Sub for Value {
+    type Output = Value;
+
+    fn sub(self, other: Value) -> Value {
+        let unit = self.unit();
+        let other = other.convert(&unit).magnitude();
+        let difference = self.magnitude().combine(other, Rational::sub, Context::sub, Complex::sub, f64::sub);
+        difference.with_unit(unit)
+    }
+}
+
+const OVERFLOW: &str = "callers check that unit exponents fit";
+
+impl Mul for Value {
+    type Output = Value;
+
+    fn mul(self, other: Value) -> Value {
+        let other = other.clone().convert(&self.unit().aligned(&other.unit()).expect(OVERFLOW));
+        let unit = self.unit().mul(&other.unit()).expect(OVERFLOW);
+        let product = self.magnitude().combine(other.magnitude(), Rational::mul, Context::mul, Complex::mul, f64::mul);
+        product.with_unit(unit)
+    }
+}
+
+impl Div for Value {
+    type Output = Value;
+
+    /// Exact or decimal division by zero panics; callers check `is_zero`
+    /// first.
+    fn div(self, other: Value) -> Value {
+        let other = other.clone().convert(&self.unit().aligned(&other.unit()).expect(OVERFLOW));
+        let unit = self.unit().div(&other.unit()).expect(OVERFLOW);
+        let decimal = |ctx: Context, a: &Decimal, b: &Decimal| ctx.div(a, b).expect("division by zero");
+        let quotient = self.magnitude().combine(other.magnitude(), Rational::div, decimal, Complex::div, f64::div);
+        quotient.with_unit(unit)
+    }
+}
+
+impl Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        match self {
+            Value::Float(v) => Value::Float(-v),
+            Value::Exact(r) => Value::Exact(-r),
+            Value::Decimal(d, ctx) => Value::Decimal(-d, ctx),
+            Value::Complex(c) => Value::Complex(-c),
+            Value::Quantity(v, unit) => Value::Quantity(Box::new(-*v), unit),
+            Value::Array(elements) => Value::Array(elements.into_iter().map(Value::neg).collect()),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+/// Quantities compare in the left one's unit; ones of different
+/// dimensions don't compare. Arrays are only equal or not.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Array(a), Value::Array(b)) => (a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x == y)).then_some(Ordering::Equal),
+            (Value::Array(_), _) | (_, Value::Array(_)) => None,
+            (a, b) if a.unit().dimension() != b.unit().dimension() => None,
+            (Value::Quantity(a, unit), b) => a.as_ref().partial_cmp(&b.clone().convert(unit).magnitude()),
+            (Value::Exact(a), Value::Exact(b)) => Some(a.cmp(b)),
+            (Value::Decimal(a, _), Value::Decimal(b, _)) => Some(a.cmp(b)),
+            (a, b) if a.is_nonreal() || b.is_nonreal() => (a.to_complex() == b.to_complex()).then_some(Ordering::Equal),
+            (a, b) => a.to_f64().partial_cmp(&b.to_f64()),
+        }
+    }
+}
+
+/// `{}` prints fractions as `1/3`; `{:#}` adds their decimal expansion,
+/// `1/3 ≈ 0.33333333333333333333` or `7/20 = 0.35`, except inside arrays.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Float(v) => write!(f, "{v}"),
+            Value::Decimal(d, _) => write!(f, "{d}"),
+            Value::Complex(c) => write!(f, "{c}"),
+            Value::Quantity(v, unit) if f.alternate() => write!(f, "{v:#} {unit}"),
+            Value::Quantity(v, unit) => write!(f, "{v} {unit}"),
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                for (i, e) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{e}")?;
+                }
+                write!(f, "]")
+            }
+            Value::Exact(r) if !f.alternate() || r.is_integer() => write!(f, "{r}"),
+            Value::Exact(r) => {
+                let (decimal, exact) = r.to_decimal(EXPANSION_DIGITS);
+                write!(f, "{r} {} {decimal}", if exact { "=" } else { "≈" })
+            }
+        }
+    }
+}